@@ -0,0 +1,156 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use aead::{Buffer, stream::{NewStream, NonceSize, StreamLE31, StreamPrimitive}};
+use aead::generic_array::GenericArray;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use crypto_common::typenum::Unsigned;
+
+use crate::Handle;
+use crate::error::StreamError;
+use crate::header::{preamble_length, Header};
+
+/// The length of the authentication tag appended to every chunk, which is the same for every
+/// supported algorithm.
+pub(crate) const TAG_LENGTH: usize = 16;
+
+/// The length of the plaintext in `chunks_length` bytes of chunks, each with `chunk_size` bytes of
+/// plaintext and a tag. Only the last chunk may be shorter.
+pub(crate) fn plaintext_length(chunks_length: u64, chunk_size: u64) -> u64 {
+    let chunk_length = chunk_size + TAG_LENGTH as u64;
+    chunks_length / chunk_length * chunk_size + (chunks_length % chunk_length).saturating_sub(TAG_LENGTH as u64)
+}
+
+/// The AEAD used with STREAM-LE31 to encrypt the chunks of a stream.
+///
+/// All algorithms use 256-bit keys. AES-256-GCM-SIV is the default and is fastest on hardware
+/// with AES instructions, while the ChaCha20-Poly1305 variants are fast and constant-time
+/// everywhere. XChaCha20-Poly1305 has a longer nonce, so its handles are longer too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    #[default]
+    Aes256GcmSiv,
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [Self::Aes256GcmSiv, Self::ChaCha20Poly1305, Self::XChaCha20Poly1305];
+
+    /// The id of the algorithm in the [header](crate::header::Header).
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Aes256GcmSiv => 1,
+            Self::ChaCha20Poly1305 => 2,
+            Self::XChaCha20Poly1305 => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Aes256GcmSiv => "aes-256-gcm-siv",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+            Self::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.name().eq_ignore_ascii_case(name)).ok_or_else(|| {
+            let names: Vec<_> = Self::ALL.iter().map(|algorithm| algorithm.name()).collect();
+            format!("unknown algorithm '{name}', expected one of {}", names.join(", "))
+        })
+    }
+}
+
+macro_rules! chunk_cipher_impl {
+    ($($variant: ident => $cipher: ty),+) => {
+        impl Algorithm {
+            /// The length of the nonce in a [`Handle`] for this algorithm.
+            pub fn nonce_length(self) -> usize {
+                match self {
+                    $(Self::$variant => NonceSize::<$cipher, StreamLE31<$cipher>>::to_usize()),+
+                }
+            }
+        }
+
+        /// STREAM-LE31 over whichever algorithm a stream uses.
+        pub(crate) enum ChunkCipher {
+            $($variant(Box<StreamLE31<$cipher>>)),+
+        }
+
+        impl ChunkCipher {
+            /// Returns `None` if the nonce of the handle does not fit the algorithm.
+            pub(crate) fn new(algorithm: Algorithm, handle: &Handle) -> Option<Self> {
+                if handle.nonce().len() != algorithm.nonce_length() {
+                    return None;
+                }
+
+                let key = GenericArray::from_slice(handle.key());
+                let nonce = handle.nonce();
+                Some(match algorithm {
+                    $(Algorithm::$variant => Self::$variant(Box::new(StreamLE31::new(key, GenericArray::from_slice(nonce))))),+
+                })
+            }
+
+            pub(crate) fn encrypt_in_place(&self, position: u32, last_chunk: bool, associated_data: &[u8], buffer: &mut dyn Buffer) -> aead::Result<()> {
+                match self {
+                    $(Self::$variant(stream) => stream.encrypt_in_place(position, last_chunk, associated_data, buffer)),+
+                }
+            }
+
+            fn try_decrypt_in_place(&self, position: u32, last_chunk: bool, associated_data: &[u8], buffer: &mut dyn Buffer) -> aead::Result<()> {
+                match self {
+                    $(Self::$variant(stream) => stream.decrypt_in_place(position, last_chunk, associated_data, buffer)),+
+                }
+            }
+        }
+    };
+}
+
+chunk_cipher_impl!(Aes256GcmSiv => Aes256GcmSiv, ChaCha20Poly1305 => ChaCha20Poly1305, XChaCha20Poly1305 => XChaCha20Poly1305);
+
+impl ChunkCipher {
+    /// Decrypts the chunk at `position`, or tells why it does not authenticate and clears it.
+    ///
+    /// A chunk that authenticates as the other kind of chunk shows that the stream was cut short
+    /// after it or goes on past it, rather than that it was tampered with. The algorithms leave a
+    /// chunk that does not authenticate untouched, so it can be tried again as the other kind.
+    pub(crate) fn decrypt_in_place(&self, position: u32, last_chunk: bool, associated_data: &[u8], buffer: &mut dyn Buffer) -> Result<(), StreamError> {
+        if self.try_decrypt_in_place(position, last_chunk, associated_data, buffer).is_ok() {
+            return Ok(());
+        }
+
+        let offset = chunk_offset(associated_data, position);
+        let end = offset + buffer.len() as u64;
+        let error = match last_chunk {
+            true if buffer.len() < TAG_LENGTH => StreamError::Truncated { offset: end },
+            _ if self.try_decrypt_in_place(position, !last_chunk, associated_data, buffer).is_err() => StreamError::Unauthenticated { chunk: position, offset },
+            true => StreamError::Truncated { offset: end },
+            false => StreamError::TrailingData { offset: end },
+        };
+        buffer.truncate(0);
+        Err(error)
+    }
+}
+
+/// Finds where the chunk at `position` starts from the associated data of its stream, which starts with the preamble.
+fn chunk_offset(associated_data: &[u8], position: u32) -> u64 {
+    let header = Header::from_bytes(associated_data).expect("the associated data starts with the preamble");
+    let preamble_length = preamble_length(associated_data).ok().flatten().expect("the associated data starts with the preamble");
+    preamble_length as u64 + position as u64 * (header.chunk_size as u64 + TAG_LENGTH as u64)
+}
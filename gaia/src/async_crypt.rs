@@ -0,0 +1,515 @@
+//! The async readers, which wrap the readers of any runtime that there is a [`PollSource`] for.
+
+#[cfg(feature = "tokio")]
+use std::collections::VecDeque;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use aead::OsRng;
+#[cfg(feature = "tokio")]
+use tokio::task::JoinHandle;
+
+use crate::{generate_handle, Handle, DecryptOptions, EncryptOptions, Identity, Metadata};
+use crate::algorithm::{plaintext_length, ChunkCipher, TAG_LENGTH};
+use crate::buffer::ChunkBuffer;
+use crate::encoding::{EncodingInput, PlaintextDecoder};
+use crate::header::{missing_preamble_bytes, HeaderError};
+use crate::options::Opened;
+use crate::recipient::write_preamble;
+
+/// A reader of some async runtime that the async readers can wrap.
+///
+/// `M` is a marker type for the runtime, which lets a reader be wrapped for every runtime whose
+/// traits it implements.
+pub(crate) trait PollSource<M> {
+    /// Reads into `buffer`, returning how many bytes were read, or 0 at the end of the input.
+    fn poll_read_into(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+/// A reader of some async runtime that [`AsyncDecryptingReader`] can seek, see [`PollSource`].
+pub(crate) trait SeekSource<M> {
+    /// Seeks to `position`. `started` is kept across pending polls of the same seek, for runtimes
+    /// that start a seek and then poll for it separately.
+    fn poll_seek_to(self: Pin<&mut Self>, cx: &mut Context<'_>, position: SeekFrom, started: &mut bool) -> Poll<io::Result<u64>>;
+}
+
+/// Reads an async reader one chunk at a time, straight into a [`ChunkBuffer`].
+///
+/// One byte past every chunk is read ahead, to tell whether the chunk is the last one. A chunk
+/// that is only partially read when the underlying reader is pending stays in the buffer until
+/// it is complete.
+struct AsyncChunkingReader<R> where R: Unpin {
+    reader: Box<R>,
+    lookahead: Option<u8>, // First byte of the next chunk
+    filling: bool, // Whether the buffer holds a partially read chunk
+    encoding: Option<EncodingInput>, // Compresses and pads what is read when encrypting
+    read: u64, // Bytes read from the reader, which tells where the stream starts in it when seeking
+}
+
+impl<R> AsyncChunkingReader<R> where R: Unpin {
+    fn new(reader: R) -> Self {
+        Self { reader: Box::new(reader), lookahead: None, filling: false, encoding: None, read: 0 }
+    }
+
+    /// Forgets anything that was read ahead, for after the underlying reader has been seeked.
+    fn reset(&mut self) {
+        self.lookahead = None;
+        self.filling = false;
+    }
+
+    /// Reads the next chunk of up to `chunk_length` bytes into `buffer`, returning whether it is
+    /// the last one. The last chunk may be empty.
+    fn poll_read_chunk<M>(&mut self, cx: &mut Context<'_>, buffer: &mut ChunkBuffer, chunk_length: usize) -> Poll<io::Result<bool>>
+    where R: PollSource<M> {
+        if !self.filling {
+            buffer.clear();
+            buffer.reserve(chunk_length + TAG_LENGTH);
+            if let Some(byte) = self.lookahead.take() {
+                buffer.push(byte);
+            }
+            self.filling = true;
+        }
+
+        let end = chunk_length + 1;
+        while buffer.len() < end {
+            let mut reader = Pin::new(self.reader.as_mut());
+            let read = match self.encoding {
+                Some(ref mut input) => input.poll_read(buffer.unfilled(end), |staging| reader.as_mut().poll_read_into(cx, staging)),
+                None => reader.poll_read_into(cx, buffer.unfilled(end)),
+            };
+            match ready!(read)? {
+                0 => break, // end-of-file
+                length => {
+                    buffer.fill(length);
+                    self.read += length as u64;
+                }
+            }
+        }
+
+        if buffer.len() == end {
+            self.lookahead = buffer.pop();
+        }
+        self.filling = false;
+        Poll::Ready(Ok(self.lookahead.is_none()))
+    }
+}
+
+/// The chunks that a reader has sent to the blocking thread pool, see [`AsyncEncryptingReader::offload`].
+#[cfg(feature = "tokio")]
+struct Offload {
+    depth: usize,
+    associated_data: Option<Arc<[u8]>>, // Shared with the tasks once the header has been handled
+    in_flight: VecDeque<JoinHandle<io::Result<(ChunkBuffer, bool)>>>, // In order, with whether each is the last chunk
+    filling: ChunkBuffer, // The next chunk while it is being read
+    spare: Vec<ChunkBuffer>, // Buffers of chunks that have been read out, for reuse
+    read_last_chunk: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl Offload {
+    fn new(depth: usize) -> Self {
+        Self { depth: depth.max(1), associated_data: None, in_flight: VecDeque::new(), filling: ChunkBuffer::new(),
+            spare: vec![], read_last_chunk: false }
+    }
+
+    /// Forgets the chunks in flight, for after the underlying reader has been seeked.
+    fn reset(&mut self) {
+        self.in_flight.clear();
+        self.read_last_chunk = false;
+    }
+}
+
+macro_rules! async_crypt_reader_impl {
+    ($name: ident, $setup: ty, $poll_header: ident, $in_place: ident) => {
+        /// Implements the `AsyncRead` trait of tokio or of `futures-io`, depending on which one the
+        /// wrapped reader implements and which features are enabled.
+        pub struct $name<R> where R: Unpin {
+            chunk_reader: AsyncChunkingReader<R>,
+            setup: $setup, // Whatever the header hook needs to set up the cipher
+            cipher: Option<Arc<ChunkCipher>>, // Set up once the header has been handled
+            associated_data: Vec<u8>,
+            position: u32, // Position of the next chunk to be transformed
+            finished: bool,
+            chunk_length: usize, // Length of a single chunk of input, including the tag when decrypting
+            buffer: ChunkBuffer, // The current chunk, transformed once it has been read completely
+            decoder: Option<PlaintextDecoder>, // Decompresses or unpads the output when decrypting
+            #[cfg(feature = "tokio")]
+            offload: Option<Offload>,
+        }
+
+        impl<R> $name<R> where R: Unpin {
+            fn with_parameters(reader: R, setup: $setup, chunk_length: usize) -> Self {
+                Self { chunk_reader: AsyncChunkingReader::new(reader), setup, cipher: None, associated_data: vec![],
+                    position: 0, finished: false, chunk_length, buffer: ChunkBuffer::new(), decoder: None,
+                    #[cfg(feature = "tokio")]
+                    offload: None,
+                }
+            }
+
+            /// Transforms the chunks on the blocking thread pool of the tokio runtime instead of in
+            /// `poll_read`, reading ahead so that up to `depth` chunks are transformed at once. This
+            /// keeps the CPU-bound work off the threads that drive other tasks.
+            ///
+            /// The reader must then be polled from within a tokio runtime.
+            #[cfg(feature = "tokio")]
+            pub fn offload(mut self, depth: usize) -> Self {
+                self.offload = Some(Offload::new(depth));
+                self
+            }
+
+            /// Reads the transformed stream into `output`, returning how many bytes were read, or 0
+            /// at the end of the stream.
+            pub(crate) fn poll_read_output<M>(&mut self, cx: &mut Context<'_>, output: &mut [u8]) -> Poll<io::Result<usize>> where R: PollSource<M> {
+                loop {
+                    ready!(self.poll_fill::<M>(cx))?;
+
+                    let Some(decoder) = self.decoder.as_mut().filter(|_| !output.is_empty()) else {
+                        let effective_length = output.len().min(self.buffer.len());
+                        output[..effective_length].copy_from_slice(&self.buffer.unread()[..effective_length]);
+                        self.buffer.consume(effective_length);
+                        return Poll::Ready(Ok(effective_length));
+                    };
+
+                    let (read, written) = decoder.decode(self.buffer.unread(), output)?;
+                    self.buffer.consume(read);
+                    if written > 0 {
+                        return Poll::Ready(Ok(written));
+                    }
+                    if self.buffer.is_empty() && self.finished {
+                        decoder.finish()?;
+                        return Poll::Ready(Ok(0));
+                    }
+                }
+            }
+
+            /// Handles the header if it has not been handled yet.
+            fn poll_header<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> where R: PollSource<M> {
+                if self.cipher.is_none() {
+                    let cipher = ready!(self.$poll_header::<M>(cx))?;
+                    self.cipher = Some(Arc::new(cipher));
+                }
+                Poll::Ready(Ok(()))
+            }
+
+            /// Handles the header and transforms chunks until there is something to read out of the
+            /// buffer, or the end of the stream has been reached.
+            fn poll_fill<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> where R: PollSource<M> {
+                ready!(self.poll_header::<M>(cx))?;
+
+                #[cfg(feature = "tokio")]
+                if self.offload.is_some() {
+                    return self.poll_transform_offloaded::<M>(cx);
+                }
+
+                self.poll_transform::<M>(cx)
+            }
+
+            fn poll_transform<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> where R: PollSource<M> {
+                let cipher = self.cipher.as_ref().expect("the header has been handled");
+                while self.chunk_reader.filling || (self.buffer.is_empty() && !self.finished) {
+                    let last_chunk = ready!(self.chunk_reader.poll_read_chunk::<M>(cx, &mut self.buffer, self.chunk_length))?;
+                    cipher.$in_place(self.position, last_chunk, &self.associated_data, &mut self.buffer)
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+                    self.position += 1;
+                    self.finished = last_chunk;
+                }
+                Poll::Ready(Ok(()))
+            }
+
+            /// Like [`poll_transform`](Self::poll_transform), but sends the chunks to the blocking thread pool.
+            #[cfg(feature = "tokio")]
+            fn poll_transform_offloaded<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> where R: PollSource<M> {
+                let cipher = self.cipher.as_ref().expect("the header has been handled");
+                let offload = self.offload.as_mut().expect("the reader is offloaded");
+                while self.buffer.is_empty() && !self.finished {
+                    while !offload.read_last_chunk && offload.in_flight.len() < offload.depth {
+                        let Poll::Ready(last_chunk) = self.chunk_reader.poll_read_chunk::<M>(cx, &mut offload.filling, self.chunk_length)? else {
+                            match offload.in_flight.is_empty() {
+                                true => return Poll::Pending,
+                                false => break, // Wait for the chunks in flight instead
+                            }
+                        };
+
+                        let mut chunk = std::mem::replace(&mut offload.filling, offload.spare.pop().unwrap_or_else(ChunkBuffer::new));
+                        let cipher = cipher.clone();
+                        let associated_data = offload.associated_data.get_or_insert_with(|| self.associated_data.as_slice().into()).clone();
+                        let position = self.position;
+                        offload.in_flight.push_back(tokio::task::spawn_blocking(move || {
+                            cipher.$in_place(position, last_chunk, &associated_data, &mut chunk)
+                                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                            Ok((chunk, last_chunk))
+                        }));
+
+                        self.position += 1;
+                        offload.read_last_chunk = last_chunk;
+                    }
+
+                    let task = offload.in_flight.front_mut().expect("chunks are in flight until the last one is read out");
+                    let (chunk, last_chunk) = ready!(Pin::new(task).poll(cx)).map_err(io::Error::other)??;
+                    offload.in_flight.pop_front();
+                    offload.spare.push(std::mem::replace(&mut self.buffer, chunk));
+                    self.finished = last_chunk;
+                }
+                Poll::Ready(Ok(()))
+            }
+        }
+    };
+}
+
+async_crypt_reader_impl!(AsyncEncryptingReader, (Handle, EncryptOptions), poll_write_header, encrypt_in_place);
+async_crypt_reader_impl!(AsyncDecryptingReader, Decryption, poll_read_header, decrypt_in_place);
+
+/// What the decrypting reader needs besides the stream itself.
+struct Decryption {
+    options: DecryptOptions,
+    metadata: Option<Metadata>, // Read from the preamble
+    start: Option<u64>, // Where the stream starts in the reader, found when it is first seeked
+    seek: Option<SeekState>,
+    seek_started: bool, // Whether the current seek of the underlying reader has been started
+}
+
+/// The steps of a seek, each of which may have to wait for the underlying reader.
+#[derive(Clone, Copy)]
+enum SeekState {
+    /// Requested, but the header may not have been read yet
+    Requested(SeekFrom),
+    /// Asking the underlying reader where it is, to find where the stream starts in it
+    FindingStart(SeekFrom),
+    /// Seeking the underlying reader to its end to find the length of the plaintext
+    FindingLength(SeekFrom),
+    /// Seeking the underlying reader to the chunk the target is in, or to the last chunk if the
+    /// target is past it, with how much of the chunk precedes the target
+    FindingChunk(u64, u64),
+    /// Decrypting the chunk the target is in
+    Decrypting(u64, u64),
+}
+
+impl<R> AsyncEncryptingReader<R> where R: Unpin {
+    pub fn new(reader: R, handle: &Handle) -> Self {
+        Self::with_options(reader, handle, &EncryptOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub fn with_options(reader: R, handle: &Handle, options: &EncryptOptions) -> Self {
+        assert_eq!(handle.nonce().len(), options.algorithm.nonce_length(), "the handle does not fit {}", options.algorithm);
+        let mut encrypting = Self::with_parameters(reader, (handle.clone(), options.clone()), options.chunk_size);
+        encrypting.chunk_reader.encoding = EncodingInput::new(options);
+        encrypting
+    }
+
+    /// Encrypts with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(reader: R, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(reader, handle, &EncryptOptions::default().associated_data(associated_data))
+    }
+
+    pub fn new_with_os_rng(reader: R) -> (Self, Handle) {
+        let handle = generate_handle(&mut OsRng);
+        (Self::new(reader, &handle), handle)
+    }
+
+    fn poll_write_header<M>(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<ChunkCipher>> where R: PollSource<M> {
+        let (handle, options) = &self.setup;
+        let preamble = write_preamble(handle, options)?;
+        self.buffer.reserve(preamble.len());
+        self.buffer.push_slice(&preamble);
+        self.associated_data = [preamble.as_slice(), &options.associated_data].concat();
+        Poll::Ready(Ok(ChunkCipher::new(options.algorithm, handle).expect("the handle was checked to fit the algorithm")))
+    }
+}
+
+impl<R> AsyncDecryptingReader<R> where R: Unpin {
+    pub fn new(reader: R, handle: &Handle) -> Self {
+        Self::with_identity(reader, Identity::Handle(handle.clone()))
+    }
+
+    /// Decrypts with any identity the stream was encrypted for, such as a passphrase.
+    pub fn with_identity(reader: R, identity: Identity) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identity(identity))
+    }
+
+    /// Decrypts with whichever of the identities the stream was encrypted for, trying them in order.
+    pub fn with_identities(reader: R, identities: impl IntoIterator<Item = Identity>) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identities(identities))
+    }
+
+    /// Decrypts a stream that was encrypted with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(reader: R, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identity(Identity::Handle(handle.clone())).associated_data(associated_data))
+    }
+
+    pub fn with_options(reader: R, options: &DecryptOptions) -> Self {
+        // The chunk length is only known once the header has been read
+        Self::with_parameters(reader, Decryption { options: options.clone(), metadata: None, start: None, seek: None, seek_started: false }, 0)
+    }
+
+    fn poll_read_header<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<ChunkCipher>> where R: PollSource<M> {
+        let invalid_data = |e: HeaderError| io::Error::new(ErrorKind::InvalidData, e);
+
+        // The preamble is collected in the associated data, which keeps it across pending reads
+        loop {
+            let missing = missing_preamble_bytes(&self.associated_data).map_err(invalid_data)?;
+            if missing == 0 {
+                break;
+            }
+
+            let mut buffer = vec![0u8; missing];
+            let read = ready!(Pin::new(self.chunk_reader.reader.as_mut()).poll_read_into(cx, &mut buffer))?;
+            self.associated_data.extend_from_slice(&buffer[..read]);
+            self.chunk_reader.read += read as u64;
+
+            if read == 0 {
+                // Short foreign input is reported as such rather than as truncated
+                missing_preamble_bytes(&self.associated_data).map_err(invalid_data)?;
+                return Poll::Ready(Err(invalid_data(HeaderError::Truncated)));
+            }
+        }
+
+        let Opened { cipher, chunk_length, associated_data, decoder, metadata } = self.setup.options.open(&self.associated_data)?;
+        self.chunk_length = chunk_length;
+        self.associated_data = associated_data;
+        self.decoder = decoder;
+        self.setup.metadata = metadata;
+        Poll::Ready(Ok(cipher))
+    }
+
+    /// Reads the preamble if it has not been read yet, and returns the metadata of the stream if
+    /// it has any, see [`EncryptOptions::metadata`].
+    ///
+    /// This is for tokio readers, see `gaia::futures::metadata` for those of `futures-io`.
+    #[cfg(feature = "tokio")]
+    pub async fn metadata(&mut self) -> io::Result<Option<&Metadata>> where R: tokio::io::AsyncRead {
+        self.read_metadata::<crate::tokio_crypt::Tokio>().await
+    }
+
+    /// Reads the preamble of a reader of any runtime, see [`metadata`](Self::metadata).
+    pub(crate) async fn read_metadata<M>(&mut self) -> io::Result<Option<&Metadata>> where R: PollSource<M> {
+        std::future::poll_fn(|cx| self.poll_header::<M>(cx)).await?;
+        Ok(self.setup.metadata.as_ref())
+    }
+
+    /// Returns the reader the ciphertext is read from.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.chunk_reader.reader
+    }
+
+    /// Starts seeking to a position in the plaintext, which [`poll_seek`](Self::poll_seek) then completes.
+    pub(crate) fn start_seek(&mut self, position: SeekFrom) -> io::Result<()> {
+        if self.setup.seek.is_some() {
+            return Err(io::Error::other("a seek was started while another one is in progress"));
+        }
+        self.setup.seek = Some(SeekState::Requested(position));
+        Ok(())
+    }
+
+    #[cfg(feature = "futures-io")]
+    pub(crate) fn is_seeking(&self) -> bool {
+        self.setup.seek.is_some()
+    }
+
+    /// Completes a seek by decrypting only the chunk the new position is in, returning the position.
+    ///
+    /// Seeking to or past the end of the plaintext decrypts the last chunk all the same, so that a
+    /// truncated stream fails to seek instead of seeming to end early. Compressed or padded plaintext
+    /// cannot be seeked.
+    pub(crate) fn poll_seek<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> where R: PollSource<M> + SeekSource<M> {
+        let result = self.poll_seek_steps::<M>(cx);
+        if let Poll::Ready(Err(_)) = result {
+            self.setup.seek = None;
+            self.setup.seek_started = false;
+        }
+        result
+    }
+
+    fn poll_seek_steps<M>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> where R: PollSource<M> + SeekSource<M> {
+        loop {
+            let start = self.setup.start.unwrap_or(0);
+            let chunks_start = start + (self.associated_data.len() - self.setup.options.associated_data.len()) as u64;
+            let chunk_size = self.chunk_length.saturating_sub(TAG_LENGTH) as u64;
+
+            match self.setup.seek {
+                // Like the readers of tokio, the position is not tracked without a seek in progress
+                None => return Poll::Ready(Ok(0)),
+                Some(SeekState::Requested(position)) => {
+                    ready!(self.poll_header::<M>(cx))?;
+                    if self.decoder.is_some() {
+                        return Poll::Ready(Err(io::Error::new(ErrorKind::Unsupported, "compressed or padded plaintext cannot be seeked")));
+                    }
+                    self.setup.seek = Some(match self.setup.start {
+                        Some(_) => SeekState::FindingLength(position),
+                        None => SeekState::FindingStart(position),
+                    });
+                }
+                Some(SeekState::FindingStart(position)) => {
+                    // The stream need not start at the start of the reader, like when it follows something else in a file
+                    let current = ready!(Pin::new(self.chunk_reader.reader.as_mut()).poll_seek_to(cx, SeekFrom::Current(0), &mut self.setup.seek_started))?;
+                    self.setup.start = Some(current - self.chunk_reader.read);
+                    self.setup.seek = Some(SeekState::FindingLength(position));
+                }
+                Some(SeekState::FindingLength(position)) => {
+                    let end = ready!(Pin::new(self.chunk_reader.reader.as_mut()).poll_seek_to(cx, SeekFrom::End(0), &mut self.setup.seek_started))?;
+                    let chunks_length = end - chunks_start;
+                    let total = plaintext_length(chunks_length, chunk_size);
+
+                    // A partially read chunk has not been decrypted yet, and chunks in flight have not been read out
+                    #[cfg(feature = "tokio")]
+                    let (unread, in_flight) = match self.offload {
+                        Some(ref offload) => (self.buffer.len() as u64, offload.in_flight.len() as u64),
+                        None if self.chunk_reader.filling => (0, 0),
+                        None => (self.buffer.len() as u64, 0),
+                    };
+                    #[cfg(not(feature = "tokio"))]
+                    let (unread, in_flight) = match self.chunk_reader.filling {
+                        true => (0, 0),
+                        false => (self.buffer.len() as u64, 0),
+                    };
+                    let target = match position {
+                        SeekFrom::Start(offset) => Some(offset),
+                        SeekFrom::End(offset) => total.checked_add_signed(offset),
+                        // Every chunk before the last one is full, and the unread plaintext is still buffered
+                        SeekFrom::Current(offset) => match self.finished {
+                            true => total - unread,
+                            false => (self.position as u64 - in_flight) * chunk_size - unread,
+                        }.checked_add_signed(offset),
+                    }.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))?;
+
+                    self.buffer.clear();
+                    self.chunk_reader.reset();
+                    #[cfg(feature = "tokio")]
+                    if let Some(ref mut offload) = self.offload {
+                        offload.reset();
+                    }
+
+                    // Seeking to or past the end still authenticates the last chunk, so that truncation is noticed
+                    let index = match target >= total {
+                        true => chunks_length.saturating_sub(1) / self.chunk_length as u64,
+                        false => target / chunk_size,
+                    };
+                    self.position = u32::try_from(index).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "seek past the last possible chunk"))?;
+                    self.finished = false;
+                    self.setup.seek = Some(SeekState::FindingChunk(target, target.min(total) - index * chunk_size));
+                }
+                Some(SeekState::FindingChunk(target, skip)) => {
+                    let chunk_start = chunks_start + self.position as u64 * self.chunk_length as u64;
+                    ready!(Pin::new(self.chunk_reader.reader.as_mut()).poll_seek_to(cx, SeekFrom::Start(chunk_start), &mut self.setup.seek_started))?;
+                    self.chunk_reader.reset();
+                    self.chunk_reader.read = chunk_start - start;
+                    self.setup.seek = Some(SeekState::Decrypting(target, skip));
+                }
+                Some(SeekState::Decrypting(target, skip)) => {
+                    // Decrypt the chunk the target is in and drop whatever precedes the target
+                    ready!(self.poll_fill::<M>(cx))?;
+                    self.buffer.consume(skip as usize);
+                    self.setup.seek = None;
+                    return Poll::Ready(Ok(target));
+                }
+            }
+        }
+    }
+}
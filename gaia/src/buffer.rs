@@ -0,0 +1,172 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// A buffer that chunks are read into, transformed in place and read out of again.
+///
+/// The buffer is allocated once, when it is first reserved, and reused for every chunk after
+/// that. Bytes are read out of it by moving the start of the unread bytes forward rather than
+/// moving the bytes themselves.
+pub(crate) struct ChunkBuffer {
+    bytes: Vec<u8>, // Allocated region, always fully initialised
+    filled: Range<usize>, // Bytes of the current chunk that have not been read out yet
+}
+
+impl ChunkBuffer {
+    pub(crate) fn new() -> Self {
+        Self { bytes: vec![], filled: 0..0 }
+    }
+
+    /// Makes room for at least `capacity` bytes, keeping the current contents.
+    pub(crate) fn reserve(&mut self, capacity: usize) {
+        if self.bytes.len() < capacity {
+            self.bytes.resize(capacity, 0);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.filled = 0..0;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.filled.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.filled.is_empty()
+    }
+
+    pub(crate) fn unread(&self) -> &[u8] {
+        &self.bytes[self.filled.clone()]
+    }
+
+    /// Marks the first `length` unread bytes as read.
+    pub(crate) fn consume(&mut self, length: usize) {
+        self.filled.start = (self.filled.start + length).min(self.filled.end);
+    }
+
+    /// Returns the free space between the end of the contents and `end`, to be read into.
+    pub(crate) fn unfilled(&mut self, end: usize) -> &mut [u8] {
+        &mut self.bytes[self.filled.end..end]
+    }
+
+    /// Marks `length` more bytes after the end of the contents as filled.
+    pub(crate) fn fill(&mut self, length: usize) {
+        self.filled.end += length;
+    }
+
+    pub(crate) fn push(&mut self, byte: u8) {
+        self.bytes[self.filled.end] = byte;
+        self.filled.end += 1;
+    }
+
+    /// # Panics
+    ///
+    /// Panics if there is no room for the bytes.
+    pub(crate) fn push_slice(&mut self, bytes: &[u8]) {
+        self.unfilled(self.filled.end + bytes.len()).copy_from_slice(bytes);
+        self.filled.end += bytes.len();
+    }
+
+    /// Adds `bytes` after the contents, like [`push_slice`](Self::push_slice), but makes room for
+    /// them first: the unread bytes are moved to the start of the buffer, which is grown if that
+    /// is not enough.
+    pub(crate) fn append(&mut self, bytes: &[u8]) {
+        if self.bytes.len() - self.filled.end < bytes.len() {
+            self.bytes.copy_within(self.filled.clone(), 0);
+            self.filled = 0..self.filled.len();
+            self.reserve(self.filled.end + bytes.len());
+        }
+        self.push_slice(bytes);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u8> {
+        if self.filled.is_empty() {
+            return None;
+        }
+        self.filled.end -= 1;
+        Some(self.bytes[self.filled.end])
+    }
+}
+
+impl AsRef<[u8]> for ChunkBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.unread()
+    }
+}
+
+impl AsMut<[u8]> for ChunkBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[self.filled.clone()]
+    }
+}
+
+/// Appends whatever is written, see [`ChunkBuffer::append`].
+impl Write for ChunkBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.append(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl aead::Buffer for ChunkBuffer {
+    /// Fails instead of growing the buffer if there is no room left.
+    fn extend_from_slice(&mut self, other: &[u8]) -> aead::Result<()> {
+        let end = self.filled.end + other.len();
+        self.bytes.get_mut(self.filled.end..end).ok_or(aead::Error)?.copy_from_slice(other);
+        self.filled.end = end;
+        Ok(())
+    }
+
+    fn truncate(&mut self, length: usize) {
+        self.filled.end = self.filled.end.min(self.filled.start + length);
+    }
+}
+
+/// A buffer over a slice that a chunk is transformed in place in, such as the part of a memory
+/// mapped file that the chunk is written to.
+#[cfg(feature = "mmap")]
+pub(crate) struct SliceBuffer<'a> {
+    bytes: &'a mut [u8],
+    length: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl<'a> SliceBuffer<'a> {
+    /// Creates a buffer over `bytes`, the first `length` of which are its contents.
+    pub(crate) fn new(bytes: &'a mut [u8], length: usize) -> Self {
+        Self { bytes, length }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for SliceBuffer<'_> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsMut<[u8]> for SliceBuffer<'_> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.length]
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl aead::Buffer for SliceBuffer<'_> {
+    /// Fails if there is no room left in the slice.
+    fn extend_from_slice(&mut self, other: &[u8]) -> aead::Result<()> {
+        let end = self.length + other.len();
+        self.bytes.get_mut(self.length..end).ok_or(aead::Error)?.copy_from_slice(other);
+        self.length = end;
+        Ok(())
+    }
+
+    fn truncate(&mut self, length: usize) {
+        self.length = self.length.min(length);
+    }
+}
@@ -0,0 +1,256 @@
+//! Encrypts and decrypts bytes that are pushed in rather than read, for the stream adapters and codecs.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{error::GaiaError, DecryptOptions, EncryptOptions, Handle};
+use crate::algorithm::{ChunkCipher, TAG_LENGTH};
+use crate::encoding::{PlaintextDecoder, PlaintextEncoder};
+use crate::header::{preamble_length, HeaderError};
+use crate::options::Opened;
+use crate::padding::PaddingTail;
+use crate::recipient::write_preamble;
+
+pub(crate) trait Chunker {
+    /// Adds `bytes` to the end of `input`.
+    fn push(&mut self, input: &mut BytesMut, bytes: &[u8]) -> Result<(), GaiaError> {
+        input.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Transforms the next chunk at the start of `input`, or returns `None` if there is not enough
+    /// input to tell whether it is the last one. Once `at_end` is set, the rest of the input ends
+    /// with the last chunk.
+    fn next_chunk(&mut self, input: &mut BytesMut, at_end: bool) -> Result<Option<Bytes>, GaiaError>;
+}
+
+pub(crate) struct ChunkEncrypter {
+    handle: Handle,
+    options: EncryptOptions,
+    cipher: Option<ChunkCipher>, // Set up once the preamble has been written
+    associated_data: Vec<u8>,
+    position: u32, // Position of the next chunk to be encrypted
+    finished: bool,
+    encoder: Option<PlaintextEncoder>, // Taken once the input has ended
+    tail: Option<PaddingTail>, // The padding that is left to add once the input has ended
+    staging: Vec<u8>, // Encoded input or padding on its way to the input
+    output: BytesMut, // Split into the chunks, which gives its allocation back once they have been dropped
+}
+
+impl ChunkEncrypter {
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub(crate) fn new(handle: &Handle, options: &EncryptOptions) -> Self {
+        assert_eq!(handle.nonce().len(), options.algorithm.nonce_length(), "the handle does not fit {}", options.algorithm);
+        Self { handle: handle.clone(), options: options.clone(), cipher: None, associated_data: vec![], position: 0, finished: false,
+            encoder: PlaintextEncoder::new(options), tail: None, staging: vec![], output: BytesMut::new() }
+    }
+}
+
+impl Chunker for ChunkEncrypter {
+    /// Compresses `bytes` first if the plaintext is compressed.
+    fn push(&mut self, input: &mut BytesMut, bytes: &[u8]) -> Result<(), GaiaError> {
+        let Some(ref mut encoder) = self.encoder else {
+            input.extend_from_slice(bytes);
+            return Ok(());
+        };
+
+        self.staging.clear();
+        encoder.encode(bytes, &mut self.staging).map_err(GaiaError::ReadingInput)?;
+        input.extend_from_slice(&self.staging);
+        Ok(())
+    }
+
+    fn next_chunk(&mut self, input: &mut BytesMut, at_end: bool) -> Result<Option<Bytes>, GaiaError> {
+        if let Some(encoder) = self.encoder.take_if(|_| at_end) {
+            self.staging.clear();
+            self.tail = Some(encoder.finish(&mut self.staging).map_err(GaiaError::ReadingInput)?);
+            input.extend_from_slice(&self.staging);
+        }
+
+        // The padding is added a chunk at a time, as there can be a lot of it
+        let chunk_size = self.options.chunk_size;
+        if let Some(tail) = self.tail.as_mut().filter(|_| input.len() <= chunk_size) {
+            self.staging.clear();
+            tail.fill(&mut self.staging, chunk_size + 1 - input.len());
+            input.extend_from_slice(&self.staging);
+        }
+        let at_end = at_end && self.tail.as_ref().is_none_or(PaddingTail::is_empty);
+
+        if self.finished || (!at_end && input.len() <= chunk_size) {
+            return Ok(None);
+        }
+
+        // The preamble goes in front of the first chunk
+        let output = &mut self.output;
+        if self.cipher.is_none() {
+            let preamble = write_preamble(&self.handle, &self.options).map_err(GaiaError::WritingOutput)?;
+            self.associated_data = [preamble.as_slice(), &self.options.associated_data].concat();
+            self.cipher = ChunkCipher::new(self.options.algorithm, &self.handle);
+            output.extend_from_slice(&preamble);
+        }
+        let cipher = self.cipher.as_ref().expect("the handle was checked to fit the algorithm");
+
+        // The chunk is encrypted right behind the preamble, so that the two are joined without copying
+        let length = input.len().min(chunk_size);
+        output.reserve(length + TAG_LENGTH);
+        let mut chunk = output.split_off(output.len());
+        chunk.extend_from_slice(&input.split_to(length));
+
+        let last_chunk = at_end && input.is_empty();
+        cipher.encrypt_in_place(self.position, last_chunk, &self.associated_data, &mut chunk).map_err(GaiaError::Encrypting)?;
+        self.position += 1;
+        self.finished = last_chunk;
+
+        output.unsplit(chunk);
+        Ok(Some(output.split().freeze()))
+    }
+}
+
+pub(crate) struct ChunkDecrypter {
+    options: DecryptOptions,
+    cipher: Option<ChunkCipher>, // Set up once the preamble has been read
+    associated_data: Vec<u8>,
+    chunk_length: usize, // Length of a single chunk of ciphertext, including the tag
+    position: u32, // Position of the next chunk to be decrypted
+    finished: bool,
+    decoder: Option<PlaintextDecoder>, // Set up with the cipher if the plaintext is compressed or padded
+    decoded: BytesMut, // Split into the decoded chunks, which gives its allocation back once they have been dropped
+}
+
+impl ChunkDecrypter {
+    pub(crate) fn new(options: &DecryptOptions) -> Self {
+        // The chunk length is only known once the preamble has been read
+        Self { options: options.clone(), cipher: None, associated_data: vec![], chunk_length: 0, position: 0, finished: false,
+            decoder: None, decoded: BytesMut::new() }
+    }
+}
+
+impl Chunker for ChunkDecrypter {
+    fn next_chunk(&mut self, input: &mut BytesMut, at_end: bool) -> Result<Option<Bytes>, GaiaError> {
+        if self.cipher.is_none() {
+            match preamble_length(input).map_err(GaiaError::InvalidHeader)? {
+                Some(length) => {
+                    let Opened { cipher, chunk_length, associated_data, decoder, .. } = self.options.open(&input.split_to(length))
+                        .map_err(GaiaError::from_output_error)?;
+                    self.cipher = Some(cipher);
+                    self.chunk_length = chunk_length;
+                    self.associated_data = associated_data;
+                    self.decoder = decoder;
+                }
+                None if at_end => return Err(GaiaError::InvalidHeader(HeaderError::Truncated)),
+                None => return Ok(None),
+            }
+        }
+        let cipher = self.cipher.as_ref().expect("the preamble has been read");
+
+        if self.finished || (!at_end && input.len() <= self.chunk_length) {
+            return Ok(None);
+        }
+
+        let mut chunk = input.split_to(input.len().min(self.chunk_length));
+        let last_chunk = at_end && input.is_empty();
+        cipher.decrypt_in_place(self.position, last_chunk, &self.associated_data, &mut chunk).map_err(GaiaError::Decrypting)?;
+        self.position += 1;
+        self.finished = last_chunk;
+
+        let Some(ref mut decoder) = self.decoder else {
+            return Ok(Some(chunk.freeze()));
+        };
+        decoder.decode_into(&chunk, &mut (&mut self.decoded).writer()).map_err(GaiaError::ReadingInput)?;
+        if last_chunk {
+            decoder.finish().map_err(GaiaError::ReadingInput)?;
+        }
+        Ok(Some(self.decoded.split().freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use bytes::BytesMut;
+
+    use crate::{generate_handle, DecryptOptions, EncryptOptions, EncryptingReader, Handle, Identity, Padding};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+    use crate::header::HeaderError;
+    use super::{ChunkDecrypter, ChunkEncrypter, Chunker};
+
+    const CHUNK_SIZE: usize = 64;
+
+    fn plaintext(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    /// Pushes `pieces` into `chunker` one at a time, taking out every chunk it can tell apart.
+    fn run(chunker: &mut impl Chunker, pieces: &[&[u8]]) -> Result<Vec<Vec<u8>>, GaiaError> {
+        let mut input = BytesMut::new();
+        let mut chunks = vec![];
+        for (index, piece) in pieces.iter().enumerate() {
+            chunker.push(&mut input, piece)?;
+            let at_end = index == pieces.len() - 1;
+            while let Some(chunk) = chunker.next_chunk(&mut input, at_end)? {
+                chunks.push(chunk.to_vec());
+            }
+        }
+        Ok(chunks)
+    }
+
+    fn streamed(plaintext: &[u8], handle: &Handle, options: &EncryptOptions) -> Vec<u8> {
+        let mut ciphertext = vec![];
+        EncryptingReader::with_options(plaintext, handle, options).read_to_end(&mut ciphertext).unwrap();
+        ciphertext
+    }
+
+    #[test]
+    fn encrypter_output_is_identical_to_streaming() {
+        let handle = generate_handle(&mut aead::OsRng);
+        for options in [EncryptOptions::new(), EncryptOptions::new().padding(Padding::Padme)] {
+            let options = options.chunk_size(CHUNK_SIZE);
+            for length in [0, 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 5] {
+                let plaintext = plaintext(length);
+                let (head, tail) = plaintext.split_at(length / 3);
+                let chunks = run(&mut ChunkEncrypter::new(&handle, &options), &[b"", head, b"", tail, b""]).unwrap();
+                assert_eq!(chunks.concat(), streamed(&plaintext, &handle, &options), "{length} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn encrypter_holds_back_a_chunk_until_it_knows_whether_it_is_the_last() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let mut encrypter = ChunkEncrypter::new(&handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE));
+        let mut input = BytesMut::from(plaintext(CHUNK_SIZE).as_slice());
+        assert!(encrypter.next_chunk(&mut input, false).unwrap().is_none());
+        assert!(encrypter.next_chunk(&mut input, true).unwrap().is_some());
+        assert!(encrypter.next_chunk(&mut input, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn decrypter_round_trip() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let options = EncryptOptions::new().chunk_size(CHUNK_SIZE);
+        let plaintext = plaintext(3 * CHUNK_SIZE + 5);
+        let ciphertext = streamed(&plaintext, &handle, &options);
+
+        let mut decrypter = ChunkDecrypter::new(&DecryptOptions::new().identity(Identity::Handle(handle)));
+        let chunks = run(&mut decrypter, &[&ciphertext[..5], b"", &ciphertext[5..100], &ciphertext[100..]]).unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks.concat(), plaintext);
+    }
+
+    #[test]
+    fn decrypter_rejects_truncated_and_extended_input() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let ciphertext = streamed(&plaintext(3 * CHUNK_SIZE), &handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE));
+        let options = DecryptOptions::new().identity(Identity::Handle(handle));
+        let decrypted = |input: &[u8]| run(&mut ChunkDecrypter::new(&options), &[input]);
+
+        assert!(matches!(decrypted(&ciphertext[..5]), Err(GaiaError::InvalidHeader(HeaderError::Truncated))));
+        let end = ciphertext.len() - CHUNK_SIZE - TAG_LENGTH;
+        assert!(matches!(decrypted(&ciphertext[..end]), Err(GaiaError::Decrypting(StreamError::Truncated { offset })) if offset == end as u64));
+        let extended = [ciphertext.as_slice(), b"more"].concat();
+        assert!(matches!(decrypted(&extended), Err(GaiaError::Decrypting(StreamError::TrailingData { offset })) if offset == ciphertext.len() as u64));
+    }
+}
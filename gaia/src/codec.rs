@@ -0,0 +1,182 @@
+//! [`tokio_util::codec`] codecs for framed transports.
+
+use std::io::{self, ErrorKind};
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{error::GaiaError, DecryptOptions, EncryptOptions, Handle, Identity};
+use crate::chunker::{ChunkDecrypter, ChunkEncrypter, Chunker};
+
+/// Encrypts the plaintext sent through a [`FramedWrite`](tokio_util::codec::FramedWrite).
+///
+/// Sending `Some` encrypts the bytes, and sending `None` ends the ciphertext with its last chunk,
+/// without which it is truncated. Nothing can be sent after that.
+pub struct EncryptingCodec {
+    chunker: ChunkEncrypter,
+    input: BytesMut, // Plaintext that has not been encrypted yet
+    finished: bool,
+}
+
+impl EncryptingCodec {
+    pub fn new(handle: &Handle) -> Self {
+        Self::with_options(handle, &EncryptOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub fn with_options(handle: &Handle, options: &EncryptOptions) -> Self {
+        Self { chunker: ChunkEncrypter::new(handle, options), input: BytesMut::new(), finished: false }
+    }
+}
+
+impl Encoder<Option<Bytes>> for EncryptingCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Option<Bytes>, dst: &mut BytesMut) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other("the ciphertext has already been ended"));
+        }
+        match item {
+            Some(bytes) => self.chunker.push(&mut self.input, &bytes).map_err(into_io_error)?,
+            None => self.finished = true,
+        }
+
+        while let Some(chunk) = self.chunker.next_chunk(&mut self.input, self.finished).map_err(into_io_error)? {
+            dst.extend_from_slice(&chunk);
+        }
+        Ok(())
+    }
+}
+
+/// Decrypts the ciphertext received through a [`FramedRead`](tokio_util::codec::FramedRead), one
+/// chunk per frame.
+pub struct DecryptingCodec {
+    chunker: ChunkDecrypter,
+}
+
+impl DecryptingCodec {
+    pub fn new(handle: &Handle) -> Self {
+        Self::with_options(&DecryptOptions::new().identity(Identity::Handle(handle.clone())))
+    }
+
+    pub fn with_options(options: &DecryptOptions) -> Self {
+        Self { chunker: ChunkDecrypter::new(options) }
+    }
+}
+
+impl Decoder for DecryptingCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        self.chunker.next_chunk(src, false).map_err(into_io_error)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        self.chunker.next_chunk(src, true).map_err(into_io_error)
+    }
+}
+
+/// Wraps the causes of an error the same way the readers do, so that they can be recovered.
+fn into_io_error(error: GaiaError) -> io::Error {
+    match error {
+        GaiaError::ReadingInput(e) | GaiaError::WritingOutput(e) => e,
+        GaiaError::Encrypting(e) => io::Error::other(e),
+        GaiaError::Decrypting(e) => io::Error::new(ErrorKind::InvalidData, e),
+        GaiaError::InvalidHeader(e) => io::Error::new(ErrorKind::InvalidData, e),
+        GaiaError::Unsealing => io::Error::new(ErrorKind::InvalidData, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::{generate_handle, EncryptOptions, EncryptingReader, Handle};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+    use super::{DecryptingCodec, EncryptingCodec};
+
+    const CHUNK_SIZE: usize = 64;
+
+    /// Sends `items` through the codec, ending the ciphertext with `None` if `end` is set.
+    fn encoded(handle: &Handle, items: &[&[u8]], end: bool) -> io::Result<Vec<u8>> {
+        let mut codec = EncryptingCodec::with_options(handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE));
+        let mut ciphertext = BytesMut::new();
+        for item in items {
+            codec.encode(Some(Bytes::copy_from_slice(item)), &mut ciphertext)?;
+        }
+        if end {
+            codec.encode(None, &mut ciphertext)?;
+        }
+        Ok(ciphertext.to_vec())
+    }
+
+    /// Receives `ciphertext` through the codec a few bytes at a time, as a framed reader would.
+    fn decoded(handle: &Handle, ciphertext: &[u8]) -> io::Result<Vec<Bytes>> {
+        let mut codec = DecryptingCodec::new(handle);
+        let mut src = BytesMut::new();
+        let mut frames = vec![];
+        for piece in ciphertext.chunks(13) {
+            src.extend_from_slice(piece);
+            while let Some(frame) = codec.decode(&mut src)? {
+                frames.push(frame);
+            }
+        }
+        while let Some(frame) = codec.decode_eof(&mut src)? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn round_trip_is_identical_to_encrypt_with_options() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE + 7).map(|i| i as u8).collect();
+        let items = [&plaintext[..1], &[], &plaintext[1..100], &plaintext[100..], &[]];
+        let ciphertext = encoded(&handle, &items, true).unwrap();
+
+        let mut streamed = vec![];
+        EncryptingReader::with_options(plaintext.as_slice(), &handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).read_to_end(&mut streamed).unwrap();
+        assert_eq!(ciphertext, streamed);
+
+        let frames = decoded(&handle, &ciphertext).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames.concat(), plaintext);
+    }
+
+    #[test]
+    fn none_ends_the_ciphertext() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let empty = encoded(&handle, &[], true).unwrap();
+        assert_eq!(decoded(&handle, &empty).unwrap().concat(), b"");
+
+        // Without the last chunk, the ciphertext is cut short
+        let unended = encoded(&handle, &[&[7; 2 * CHUNK_SIZE]], false).unwrap();
+        let error = GaiaError::from_input_error(decoded(&handle, &unended).unwrap_err());
+        assert!(matches!(error, GaiaError::Decrypting(StreamError::Truncated { offset }) if offset == unended.len() as u64));
+
+        let mut codec = EncryptingCodec::new(&handle);
+        codec.encode(None, &mut BytesMut::new()).unwrap();
+        assert!(codec.encode(Some(Bytes::from_static(b"late")), &mut BytesMut::new()).is_err());
+        assert!(codec.encode(None, &mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn damaged_ciphertext_is_classified() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let ciphertext = encoded(&handle, &[&[7; 3 * CHUNK_SIZE]], true).unwrap();
+        let error = |input: &[u8]| GaiaError::from_input_error(decoded(&handle, input).unwrap_err());
+
+        let end = ciphertext.len() - CHUNK_SIZE - TAG_LENGTH;
+        assert!(matches!(error(&ciphertext[..end]), GaiaError::Decrypting(StreamError::Truncated { offset }) if offset == end as u64));
+        let extended = [ciphertext.as_slice(), b"more"].concat();
+        assert!(matches!(error(&extended), GaiaError::Decrypting(StreamError::TrailingData { offset }) if offset == ciphertext.len() as u64));
+        assert!(matches!(decoded(&generate_handle(&mut aead::OsRng), &ciphertext).map_err(GaiaError::from_input_error),
+            Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 0, .. }))));
+    }
+}
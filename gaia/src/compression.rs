@@ -0,0 +1,135 @@
+//! Compresses the plaintext with zstd before it is split into chunks, see [`Compression`].
+
+use std::io::{self, ErrorKind};
+
+use zstd::stream::raw::{Decoder, Encoder, InBuffer, Operation, OutBuffer};
+
+/// How the plaintext is compressed before it is encrypted, see [`EncryptOptions::compression`](crate::EncryptOptions::compression).
+///
+/// Compressed ciphertexts are marked in their header, so decryption decompresses them without
+/// being told to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// The plaintext is encrypted as it is.
+    #[default]
+    None,
+    /// The plaintext is compressed with zstd at the given level, see [`zstd::compression_level_range`].
+    Zstd(i32),
+}
+
+/// A zstd frame that is written a piece at a time.
+pub(crate) struct Compressor {
+    encoder: Encoder<'static>,
+}
+
+impl Compressor {
+    /// Sets up compression, or returns `None` if there is none.
+    pub(crate) fn new(compression: Compression) -> Option<Self> {
+        match compression {
+            Compression::None => None,
+            Compression::Zstd(level) => Some(Self { encoder: Encoder::new(level).expect("the compression level was checked") }),
+        }
+    }
+
+    /// Compresses `input`, appending whatever zstd has compressed so far to `output`.
+    pub(crate) fn compress(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        while !input.is_empty() {
+            output.reserve(zstd::zstd_safe::CCtx::out_size());
+            let mut in_buffer = InBuffer::around(input);
+            let mut out_buffer = OutBuffer::around_pos(output, output.len());
+            self.encoder.run(&mut in_buffer, &mut out_buffer)?;
+            input = &input[in_buffer.pos()..];
+        }
+        Ok(())
+    }
+
+    /// Ends the frame, appending the rest of it to `output`.
+    pub(crate) fn finish(mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        loop {
+            output.reserve(zstd::zstd_safe::CCtx::out_size());
+            let mut out_buffer = OutBuffer::around_pos(output, output.len());
+            if self.encoder.finish(&mut out_buffer, true)? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Decompresses zstd frames a piece at a time.
+pub(crate) struct Decompressor {
+    decoder: Decoder<'static>,
+    frame_ended: bool, // Whether everything decompressed so far makes up whole frames
+}
+
+impl Decompressor {
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Self { decoder: Decoder::new()?, frame_ended: false })
+    }
+
+    /// Decompresses as much of `input` into `output` as fits, returning how many bytes were
+    /// read from the input and written to the output.
+    pub(crate) fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)> {
+        let status = self.decoder.run_on_buffers(input, output).map_err(corrupt)?;
+        if status.bytes_read > 0 || status.bytes_written > 0 {
+            self.frame_ended = status.remaining == 0;
+        }
+        Ok((status.bytes_read, status.bytes_written))
+    }
+
+    /// Checks that the compressed plaintext did not end in the middle of a frame.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        match self.frame_ended {
+            true => Ok(()),
+            false => Err(corrupt("the compressed plaintext ends in the middle of a frame")),
+        }
+    }
+}
+
+/// Wraps an error of zstd, which can only be caused by plaintext that was not compressed by Gaia.
+fn corrupt(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{decrypt, encrypt_with_options, Compression, DecryptingReader, EncryptOptions};
+    use crate::header::{Header, FLAG_ZSTD};
+
+    fn round_trip(plaintext: &[u8]) -> Vec<u8> {
+        let options = EncryptOptions::new().chunk_size(64).compression(Compression::Zstd(3));
+        let mut ciphertext = vec![];
+        let handle = encrypt_with_options(plaintext, &mut ciphertext, &options).unwrap();
+
+        let mut decrypted = vec![];
+        decrypt(ciphertext.as_slice(), &handle, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        // Reading through a small buffer stops decompression in the middle of chunks
+        let mut reader = DecryptingReader::new(ciphertext.as_slice(), &handle);
+        let mut decrypted = vec![];
+        let mut buffer = [0; 7];
+        loop {
+            match reader.read(&mut buffer).unwrap() {
+                0 => break,
+                read => decrypted.extend_from_slice(&buffer[..read]),
+            }
+        }
+        assert_eq!(decrypted, plaintext);
+        ciphertext
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let ciphertext = round_trip(&[]);
+        assert_ne!(Header::from_bytes(&ciphertext).unwrap().flags & FLAG_ZSTD, 0);
+
+        let plaintext = b"All work and no play makes Jack a dull boy. ".repeat(100);
+        let ciphertext = round_trip(&plaintext);
+        assert!(ciphertext.len() < plaintext.len() / 4);
+
+        let incompressible: Vec<u8> = (0..1000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        round_trip(&incompressible);
+    }
+}
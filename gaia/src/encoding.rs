@@ -0,0 +1,231 @@
+//! Compresses and pads the plaintext before it is split into chunks, and undoes it after the
+//! chunks have been decrypted, see [`Compression`](crate::Compression) and [`Padding`](crate::Padding).
+
+use std::io::{self, Read, Write};
+use std::task::{Poll, ready};
+
+use crate::EncryptOptions;
+#[cfg(feature = "compression")]
+use crate::compression::{Compressor, Decompressor};
+use crate::padding::{Padding, PaddingTail, Unpadder};
+
+/// The amount of plaintext encoded or decoded at a time.
+const STAGING_SIZE: usize = 1 << 14;
+
+/// Compresses the plaintext, and pads it once it has ended.
+pub(crate) struct PlaintextEncoder {
+    #[cfg(feature = "compression")]
+    compressor: Option<Compressor>,
+    padding: Padding,
+    length: u64, // Length of the encoded plaintext so far
+}
+
+impl PlaintextEncoder {
+    /// Sets up the encoding that the options ask for, or returns `None` if they ask for none.
+    pub(crate) fn new(options: &EncryptOptions) -> Option<Self> {
+        (options.compresses() || options.padding != Padding::None).then_some(Self {
+            #[cfg(feature = "compression")]
+            compressor: Compressor::new(options.compression),
+            padding: options.padding,
+            length: 0,
+        })
+    }
+
+    /// Encodes `input`, appending whatever has been encoded so far to `output`.
+    pub(crate) fn encode(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let start = output.len();
+        #[cfg(feature = "compression")]
+        if let Some(ref mut compressor) = self.compressor {
+            compressor.compress(input, output)?;
+            self.length += (output.len() - start) as u64;
+            return Ok(());
+        }
+
+        output.extend_from_slice(input);
+        self.length += (output.len() - start) as u64;
+        Ok(())
+    }
+
+    /// Appends the rest of the encoded plaintext to `output`, and returns the padding that follows it.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables, clippy::ptr_arg))]
+    pub(crate) fn finish(self, output: &mut Vec<u8>) -> io::Result<PaddingTail> {
+        #[cfg(feature = "compression")]
+        if let Some(compressor) = self.compressor {
+            let start = output.len();
+            compressor.finish(output)?;
+            return Ok(PaddingTail::new(self.padding, self.length + (output.len() - start) as u64, true));
+        }
+
+        Ok(PaddingTail::new(self.padding, self.length, false))
+    }
+}
+
+/// Reads plaintext and encodes it, for the encrypting readers.
+pub(crate) struct EncodingInput {
+    encoder: Option<PlaintextEncoder>, // Taken once the plaintext has ended
+    tail: Option<PaddingTail>, // Set once the plaintext has ended
+    staging: Vec<u8>, // Plaintext that is being encoded
+    encoded: Vec<u8>,
+    consumed: usize, // How much of the encoded bytes have been read out
+}
+
+impl EncodingInput {
+    pub(crate) fn new(options: &EncryptOptions) -> Option<Self> {
+        let encoder = PlaintextEncoder::new(options)?;
+        Some(Self { encoder: Some(encoder), tail: None, staging: vec![0; STAGING_SIZE], encoded: vec![], consumed: 0 })
+    }
+
+    /// Reads encoded bytes into `buffer`, reading plaintext with `read` when there are none left.
+    /// Returns 0 once the padding has been read as well.
+    pub(crate) fn poll_read(&mut self, buffer: &mut [u8], mut read: impl FnMut(&mut [u8]) -> Poll<io::Result<usize>>) -> Poll<io::Result<usize>> {
+        while self.consumed == self.encoded.len() {
+            if let Some(ref mut tail) = self.tail {
+                if tail.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                self.encoded.clear();
+                self.consumed = 0;
+                tail.fill(&mut self.encoded, STAGING_SIZE);
+                continue;
+            }
+
+            let length = ready!(read(&mut self.staging))?;
+            self.encoded.clear();
+            self.consumed = 0;
+            let encoder = self.encoder.as_mut().expect("the encoder is only taken with the tail set");
+            match length {
+                0 => self.tail = Some(self.encoder.take().expect("the plaintext has not ended").finish(&mut self.encoded)?),
+                _ => encoder.encode(&self.staging[..length], &mut self.encoded)?,
+            }
+        }
+
+        let length = buffer.len().min(self.encoded.len() - self.consumed);
+        buffer[..length].copy_from_slice(&self.encoded[self.consumed..self.consumed + length]);
+        self.consumed += length;
+        Poll::Ready(Ok(length))
+    }
+
+    pub(crate) fn read(&mut self, reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.poll_read(buffer, |staging| Poll::Ready(reader.read(staging))) {
+            Poll::Ready(result) => result,
+            Poll::Pending => unreachable!("reading synchronously is never pending"),
+        }
+    }
+}
+
+/// A reader that encodes another reader.
+#[cfg(feature = "parallel")]
+pub(crate) struct EncodingReader<R> where R: Read {
+    reader: R,
+    input: EncodingInput,
+}
+
+#[cfg(feature = "parallel")]
+impl<R> EncodingReader<R> where R: Read {
+    pub(crate) fn new(reader: R, input: EncodingInput) -> Self {
+        Self { reader, input }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<R> Read for EncodingReader<R> where R: Read {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.input.read(&mut self.reader, buffer)
+    }
+}
+
+/// Undoes the encoding of the plaintext a piece at a time.
+pub(crate) struct PlaintextDecoder {
+    decoding: Decoding,
+    staging: Vec<u8>, // Decoded plaintext on its way to a writer, allocated once it is first needed
+}
+
+/// The padding of compressed plaintext is skipped by zstd, so only one of the two is ever needed.
+enum Decoding {
+    #[cfg(feature = "compression")]
+    Decompress(Decompressor),
+    Unpad(Unpadder),
+}
+
+impl Decoding {
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)> {
+        match self {
+            #[cfg(feature = "compression")]
+            Self::Decompress(decompressor) => decompressor.decompress(input, output),
+            Self::Unpad(unpadder) => Ok(unpadder.unpad(input, output)),
+        }
+    }
+}
+
+impl PlaintextDecoder {
+    #[cfg(feature = "compression")]
+    pub(crate) fn decompress() -> io::Result<Self> {
+        Ok(Self { decoding: Decoding::Decompress(Decompressor::new()?), staging: vec![] })
+    }
+
+    pub(crate) fn unpad() -> Self {
+        Self { decoding: Decoding::Unpad(Unpadder::default()), staging: vec![] }
+    }
+
+    /// Decodes as much of `input` into `output` as fits, returning how many bytes were read from
+    /// the input and written to the output.
+    pub(crate) fn decode(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)> {
+        self.decoding.decode(input, output)
+    }
+
+    /// Decodes all of `input` into `writer`, a piece at a time.
+    pub(crate) fn decode_into(&mut self, mut input: &[u8], writer: &mut impl Write) -> io::Result<()> {
+        self.staging.resize(STAGING_SIZE, 0);
+        loop {
+            let (read, written) = self.decoding.decode(input, &mut self.staging)?;
+            writer.write_all(&self.staging[..written])?;
+            input = &input[read..];
+
+            // A full output buffer may hide more output even once the input has been read
+            if input.is_empty() && written < self.staging.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks that the plaintext ended where its encoding says it should.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        match self.decoding {
+            #[cfg(feature = "compression")]
+            Decoding::Decompress(ref decompressor) => decompressor.finish(),
+            Decoding::Unpad(ref unpadder) => unpadder.finish(),
+        }
+    }
+}
+
+/// A writer that decodes into another writer, for the functions that write their output in one go.
+#[cfg(feature = "parallel")]
+pub(crate) struct DecodingWriter<W> where W: Write {
+    writer: W,
+    decoder: PlaintextDecoder,
+}
+
+#[cfg(feature = "parallel")]
+impl<W> DecodingWriter<W> where W: Write {
+    pub(crate) fn new(writer: W, decoder: PlaintextDecoder) -> Self {
+        Self { writer, decoder }
+    }
+
+    /// Checks that the plaintext ended where its encoding says it should and flushes the underlying writer.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.decoder.finish()?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<W> Write for DecodingWriter<W> where W: Write {
+    fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+        self.decoder.decode_into(input, &mut self.writer)?;
+        Ok(input.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -1,11 +1,53 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
+use crate::header::HeaderError;
+
+/// Everything that can go wrong when encrypting or decrypting.
+///
+/// Whether the input is not a supported Gaia ciphertext is told by [`InvalidHeader`](Self::InvalidHeader),
+/// whether its chunks do not decrypt by [`Decrypting`](Self::Decrypting), and I/O errors are told
+/// apart by whether they happened while reading the input or writing the output. A message
+/// from [`seal`](crate::seal) that does not open is told by [`Unsealing`](Self::Unsealing).
 pub enum GaiaError {
     ReadingInput(std::io::Error),
     WritingOutput(std::io::Error),
     Encrypting(aead::Error),
-    Decrypting(aead::Error),
+    Decrypting(StreamError),
+    InvalidHeader(HeaderError),
+    Unsealing,
+}
+
+impl GaiaError {
+    /// Converts an error from reading one of the readers, recovering the [`HeaderError`]s and
+    /// [`StreamError`]s that the readers wrap into their [`std::io::Error`]s. Any other error is
+    /// one of the underlying reader.
+    pub fn from_input_error(error: std::io::Error) -> Self {
+        Self::recover(error).unwrap_or_else(Self::ReadingInput)
+    }
+
+    /// Converts an error from copying out of one of the readers, like [`from_input_error`](Self::from_input_error),
+    /// but for when the error may just as well have come from the output, which any other error
+    /// is then put down to.
+    pub fn from_output_error(error: std::io::Error) -> Self {
+        Self::recover(error).unwrap_or_else(Self::WritingOutput)
+    }
+
+    fn recover(error: std::io::Error) -> Result<Self, std::io::Error> {
+        let Some(cause) = error.get_ref() else {
+            return Err(error);
+        };
+        if let Some(cause) = cause.downcast_ref::<HeaderError>() {
+            return Ok(Self::InvalidHeader(*cause));
+        }
+        if let Some(cause) = cause.downcast_ref::<StreamError>() {
+            return Ok(Self::Decrypting(*cause));
+        }
+        match cause.downcast_ref::<aead::Error>() {
+            Some(cause) => Ok(Self::Encrypting(*cause)),
+            None => Err(error),
+        }
+    }
 }
 
 impl<'a> Debug for GaiaError {
@@ -15,6 +57,8 @@ impl<'a> Debug for GaiaError {
             Self::WritingOutput(_) => write!(f, "failed to write output"),
             Self::Encrypting(_) => write!(f, "failed to encrypt block"),
             Self::Decrypting(_) => write!(f, "failed to decrypt block"),
+            Self::InvalidHeader(_) => write!(f, "failed to parse header"),
+            Self::Unsealing => write!(f, "failed to open sealed message"),
         }
     }
 }
@@ -24,8 +68,10 @@ impl<'a> Display for GaiaError {
         match self {
             Self::ReadingInput(_) => write!(f, "could not read the input file"),
             Self::Encrypting(_) => write!(f, "could not encrypt the file"),
-            Self::Decrypting(_) => write!(f, "could not decrypt the file"),
-            Self::WritingOutput(_) => write!(f, "could not make the output file"),
+            Self::Decrypting(cause) => write!(f, "{cause}"),
+            Self::WritingOutput(_) => write!(f, "could not write the output"),
+            Self::InvalidHeader(cause) => write!(f, "{cause}"),
+            Self::Unsealing => write!(f, "the message cannot be opened: the key or associated data is wrong or the message has been tampered with"),
         }
     }
 }
@@ -33,8 +79,50 @@ impl<'a> Display for GaiaError {
 impl Error for GaiaError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            Self::Encrypting(cause) | Self::Decrypting(cause) => Some(cause),
+            Self::Encrypting(cause) => Some(cause),
+            Self::Decrypting(cause) => Some(cause),
             Self::WritingOutput(.., cause) | Self::ReadingInput(.., cause) => Some(cause),
+            Self::InvalidHeader(cause) => Some(cause),
+            Self::Unsealing => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Why the chunks of a stream could not be decrypted. Offsets are in bytes from the start of the
+/// ciphertext, preamble included.
+///
+/// The decrypting readers and writers return these wrapped in an [`std::io::Error`] of kind
+/// [`InvalidData`](std::io::ErrorKind::InvalidData), which [`GaiaError::from_input_error`] or
+/// [`std::io::Error::get_ref`] get them back out of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StreamError {
+    /// A chunk does not authenticate, because the key is wrong or the chunk has been tampered with.
+    /// Data added to or cut off the end of a stream in the middle of its last chunk shows up as this too.
+    Unauthenticated { chunk: u32, offset: u64 },
+    /// The stream ends at `offset`, before its last chunk.
+    Truncated { offset: u64 },
+    /// Data follows the last chunk, which ends at `offset`.
+    TrailingData { offset: u64 },
+}
+
+impl Debug for StreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthenticated { chunk, offset } => write!(f, "chunk {chunk} at byte {offset} does not authenticate"),
+            Self::Truncated { offset } => write!(f, "stream ends at byte {offset} before the last chunk"),
+            Self::TrailingData { offset } => write!(f, "data follows the last chunk at byte {offset}"),
+        }
+    }
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthenticated { chunk, offset } => write!(f, "chunk {chunk} of the input (at byte {offset}) cannot be decrypted: the key is wrong or the input has been tampered with"),
+            Self::Truncated { offset } => write!(f, "the input has been cut short: it ends at byte {offset}, before its last chunk"),
+            Self::TrailingData { offset } => write!(f, "the input goes on after its last chunk, which ends at byte {offset}"),
+        }
+    }
+}
+
+impl Error for StreamError {}
@@ -0,0 +1,274 @@
+//! Encryption and decryption for the `AsyncRead` and `AsyncWrite` traits of [`futures_io`], as
+//! used by runtimes other than tokio.
+//!
+//! [`AsyncEncryptingReader`] and [`AsyncDecryptingReader`] implement [`AsyncRead`] for any reader
+//! that implements it, and [`AsyncDecryptingReader`] implements [`AsyncSeek`] for any reader that
+//! implements both. The metadata of a stream is read with [`metadata`].
+
+use std::future::poll_fn;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use aead::OsRng;
+use futures_io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::{error::GaiaError, Handle, BUF_SIZE, DecryptOptions, EncryptOptions, Identity, Metadata, Recipient};
+use crate::async_crypt::{AsyncDecryptingReader, AsyncEncryptingReader, PollSource, SeekSource};
+
+pub async fn encrypt_async(input: impl AsyncRead + Unpin, output: impl AsyncWrite + Unpin) -> Result<Handle, GaiaError> {
+    encrypt_async_with_options(input, output, &EncryptOptions::default()).await
+}
+
+pub async fn encrypt_async_with_options(input: impl AsyncRead + Unpin, mut output: impl AsyncWrite + Unpin, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let handle = Handle::generate(options.algorithm, &mut OsRng);
+    let mut reader = AsyncEncryptingReader::with_options(input, &handle, options);
+    copy(&mut reader, &mut output).await?;
+    Ok(handle)
+}
+
+pub async fn encrypt_async_for(input: impl AsyncRead + Unpin, output: impl AsyncWrite + Unpin, recipients: &[Recipient]) -> Result<Handle, GaiaError> {
+    encrypt_async_with_options(input, output, &EncryptOptions::default().recipients(recipients.iter().cloned())).await
+}
+
+pub async fn decrypt_async(input: impl AsyncRead + Unpin, handle: &Handle, output: impl AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_identity(input, &Identity::Handle(handle.clone()), output).await
+}
+
+pub async fn decrypt_async_with_identity(input: impl AsyncRead + Unpin, identity: &Identity, output: impl AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_identities(input, std::slice::from_ref(identity), output).await
+}
+
+pub async fn decrypt_async_with_identities(input: impl AsyncRead + Unpin, identities: &[Identity], output: impl AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_options(input, &DecryptOptions::new().identities(identities.iter().cloned()), output).await
+}
+
+pub async fn decrypt_async_with_options(input: impl AsyncRead + Unpin, options: &DecryptOptions, mut output: impl AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    let mut reader = AsyncDecryptingReader::with_options(input, options);
+    copy(&mut reader, &mut output).await
+}
+
+/// Authenticates every chunk of `input` without writing the plaintext anywhere, like [`verify`](crate::verify).
+pub async fn verify_async(input: impl AsyncRead + Unpin, handle: &Handle) -> Result<(), GaiaError> {
+    verify_async_with_options(input, &DecryptOptions::new().identity(Identity::Handle(handle.clone()))).await
+}
+
+pub async fn verify_async_with_options(input: impl AsyncRead + Unpin, options: &DecryptOptions) -> Result<(), GaiaError> {
+    decrypt_async_with_options(input, options, Sink).await
+}
+
+/// Reads the preamble of `reader` if it has not been read yet, and returns the metadata of the
+/// stream if it has any, see [`EncryptOptions::metadata`].
+///
+/// This is what `AsyncDecryptingReader::metadata` does for tokio readers.
+pub async fn metadata<R>(reader: &mut AsyncDecryptingReader<R>) -> io::Result<Option<&Metadata>> where R: AsyncRead + Unpin {
+    reader.read_metadata::<Futures>().await
+}
+
+/// Copies all of `reader` into `writer` and flushes it, as `futures-io` only has the traits.
+/// Errors of the reader are told apart from those of the writer.
+async fn copy(reader: &mut (impl AsyncRead + Unpin), writer: &mut (impl AsyncWrite + Unpin)) -> Result<(), GaiaError> {
+    let mut buffer = vec![0u8; BUF_SIZE];
+    loop {
+        let read = poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, &mut buffer)).await.map_err(GaiaError::from_input_error)?;
+        if read == 0 {
+            return poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await.map_err(GaiaError::WritingOutput);
+        }
+
+        let mut written = 0;
+        while written < read {
+            match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, &buffer[written..read])).await.map_err(GaiaError::WritingOutput)? {
+                0 => return Err(GaiaError::WritingOutput(ErrorKind::WriteZero.into())),
+                length => written += length,
+            }
+        }
+    }
+}
+
+/// Discards whatever is written to it, like [`std::io::Sink`], as `futures-io` only has the traits.
+struct Sink;
+
+impl AsyncWrite for Sink {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buffer: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buffer.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Marks the readers of `futures-io`, see [`PollSource`].
+pub(crate) enum Futures {}
+
+impl<R> PollSource<Futures> for R where R: AsyncRead + ?Sized {
+    fn poll_read_into(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.poll_read(cx, buffer)
+    }
+}
+
+impl<R> SeekSource<Futures> for R where R: AsyncSeek + ?Sized {
+    fn poll_seek_to(self: Pin<&mut Self>, cx: &mut Context<'_>, position: SeekFrom, _started: &mut bool) -> Poll<io::Result<u64>> {
+        self.poll_seek(cx, position)
+    }
+}
+
+macro_rules! futures_reader_impl {
+    ($name: ident) => {
+        impl<R> AsyncRead for $name<R> where R: AsyncRead + Unpin {
+            fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut [u8]) -> Poll<io::Result<usize>> {
+                self.get_mut().poll_read_output::<Futures>(cx, output)
+            }
+        }
+    };
+}
+
+futures_reader_impl!(AsyncEncryptingReader);
+futures_reader_impl!(AsyncDecryptingReader);
+
+/// Seeks to a position in the plaintext by decrypting only the chunk it is in.
+///
+/// Seeking to or past the end of the plaintext decrypts the last chunk all the same, so that a
+/// truncated stream fails to seek instead of seeming to end early.
+impl<R> AsyncSeek for AsyncDecryptingReader<R> where R: AsyncRead + AsyncSeek + Unpin {
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context<'_>, position: SeekFrom) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        // The same position is passed again while the seek is pending
+        if !this.is_seeking() {
+            this.start_seek(position)?;
+        }
+        this.poll_seek::<Futures>(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::SeekFrom;
+
+    use futures_executor::block_on;
+    use futures_util::io::{AsyncReadExt, AsyncSeekExt, Cursor};
+
+    use crate::{generate_handle, AsyncDecryptingReader, DecryptOptions, EncryptOptions, Handle, Identity, Metadata};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+    use crate::header::HeaderError;
+    use super::{decrypt_async, decrypt_async_with_options, encrypt_async_with_options, metadata, verify_async};
+
+    const PLAINTEXT: &[u8] = b"Hello, world!";
+    const CHUNK_SIZE: usize = 64;
+
+    fn encrypted(plaintext: &[u8], options: EncryptOptions) -> (Vec<u8>, Handle) {
+        let mut ciphertext = vec![];
+        let handle = block_on(encrypt_async_with_options(plaintext, &mut ciphertext, &options.chunk_size(CHUNK_SIZE))).unwrap();
+        (ciphertext, handle)
+    }
+
+    #[test]
+    fn round_trip() {
+        block_on(async {
+            let options = EncryptOptions::new().associated_data("context");
+            let mut ciphertext = vec![];
+            let handle = encrypt_async_with_options(PLAINTEXT, &mut ciphertext, &options).await.unwrap();
+
+            let options = DecryptOptions::new().identity(Identity::Handle(handle)).associated_data("context");
+            let mut plaintext = vec![];
+            decrypt_async_with_options(ciphertext.as_slice(), &options, &mut plaintext).await.unwrap();
+            assert_eq!(plaintext, PLAINTEXT);
+        });
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        block_on(async {
+            let mut ciphertext = vec![];
+            encrypt_async_with_options(PLAINTEXT, &mut ciphertext, &EncryptOptions::new()).await.unwrap();
+
+            let other = generate_handle(&mut aead::OsRng);
+            let result = decrypt_async(ciphertext.as_slice(), &other, &mut vec![]).await;
+            assert!(matches!(result, Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 0, .. }))));
+            assert!(verify_async(ciphertext.as_slice(), &other).await.is_err());
+        });
+    }
+
+    #[test]
+    fn wrong_associated_data_fails() {
+        block_on(async {
+            let mut ciphertext = vec![];
+            let handle = encrypt_async_with_options(PLAINTEXT, &mut ciphertext, &EncryptOptions::new().associated_data("context")).await.unwrap();
+
+            let identity = Identity::Handle(handle);
+            for associated_data in ["other context", ""] {
+                let options = DecryptOptions::new().identity(identity.clone()).associated_data(associated_data);
+                let mut plaintext = vec![];
+                let result = decrypt_async_with_options(ciphertext.as_slice(), &options, &mut plaintext).await;
+                assert!(matches!(result, Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 0, .. }))), "{associated_data:?}");
+                assert!(plaintext.is_empty());
+            }
+        });
+    }
+
+    #[test]
+    fn stream_errors_are_classified() {
+        let (ciphertext, handle) = encrypted(&[7; 3 * CHUNK_SIZE], EncryptOptions::new());
+        let decrypted = |ciphertext: &[u8]| block_on(decrypt_async(ciphertext, &handle, &mut vec![]));
+        let chunk_length = CHUNK_SIZE + TAG_LENGTH;
+        let chunks_start = ciphertext.len() - 3 * chunk_length;
+
+        let mut tampered = ciphertext.clone();
+        tampered[chunks_start + chunk_length] ^= 1;
+        let offset = (chunks_start + chunk_length) as u64;
+        assert!(matches!(decrypted(&tampered), Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 1, offset: o })) if o == offset));
+
+        let offset = (ciphertext.len() - chunk_length) as u64;
+        assert!(matches!(decrypted(&ciphertext[..offset as usize]), Err(GaiaError::Decrypting(StreamError::Truncated { offset: o })) if o == offset));
+
+        let extended = [ciphertext.as_slice(), &[0]].concat();
+        let offset = ciphertext.len() as u64;
+        assert!(matches!(decrypted(&extended), Err(GaiaError::Decrypting(StreamError::TrailingData { offset: o })) if o == offset));
+
+        assert!(matches!(decrypted(b"not a Gaia ciphertext"), Err(GaiaError::InvalidHeader(HeaderError::NotGaia))));
+        assert!(matches!(decrypted(&[]), Err(GaiaError::InvalidHeader(HeaderError::Truncated))));
+    }
+
+    #[test]
+    fn seek_lands_in_the_right_chunk() {
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let (ciphertext, handle) = encrypted(&plaintext, EncryptOptions::new());
+
+        block_on(async {
+            // The stream does not have to start at the beginning of its reader
+            let mut input = Cursor::new([b"prefix".as_slice(), &ciphertext].concat());
+            input.set_position(6);
+            let mut reader = AsyncDecryptingReader::new(input, &handle);
+            for position in [CHUNK_SIZE + 1, 0, 3 * CHUNK_SIZE + 9, 2 * CHUNK_SIZE] {
+                assert_eq!(reader.seek(SeekFrom::Start(position as u64)).await.unwrap(), position as u64);
+                let mut rest = vec![];
+                reader.read_to_end(&mut rest).await.unwrap();
+                assert_eq!(rest, &plaintext[position..], "from {position}");
+            }
+
+            let truncated = &ciphertext[..ciphertext.len() - 10 - TAG_LENGTH];
+            assert!(AsyncDecryptingReader::new(Cursor::new(truncated), &handle).seek(SeekFrom::End(0)).await.is_err());
+        });
+    }
+
+    #[test]
+    fn metadata_is_read_from_the_preamble() {
+        let sealed = Metadata::new().name("ILOVEYOU.txt").mime_type("text/plain");
+        let (ciphertext, handle) = encrypted(PLAINTEXT, EncryptOptions::new().metadata(sealed.clone()));
+        let (without, other) = encrypted(PLAINTEXT, EncryptOptions::new());
+
+        block_on(async {
+            let mut reader = AsyncDecryptingReader::new(ciphertext.as_slice(), &handle);
+            assert_eq!(metadata(&mut reader).await.unwrap(), Some(&sealed));
+            let mut plaintext = vec![];
+            reader.read_to_end(&mut plaintext).await.unwrap();
+            assert_eq!(plaintext, PLAINTEXT);
+
+            assert_eq!(metadata(&mut AsyncDecryptingReader::new(without.as_slice(), &other)).await.unwrap(), None);
+        });
+    }
+}
@@ -0,0 +1,382 @@
+//! The header that precedes every Gaia ciphertext.
+//!
+//! The header lets a ciphertext describe itself: it starts with [`MAGIC`] so that Gaia files can be
+//! told apart from anything else, and records the format version, algorithm, flags and chunk size
+//! that were used to produce it. It is laid out as follows, with all integers in little-endian:
+//!
+//! | Offset | Length | Field        |
+//! |--------|--------|--------------|
+//! | 0      | 4      | magic bytes  |
+//! | 4      | 1      | version      |
+//! | 5      | 1      | algorithm id |
+//! | 6      | 2      | flags        |
+//! | 8      | 4      | chunk size   |
+//!
+//! If the [`FLAG_RECIPIENTS`] flag is set, the header is followed by a list of [`Stanza`]s, which
+//! hold the key of the stream wrapped for each recipient that can decrypt it:
+//!
+//! | Length | Field                                  |
+//! |--------|----------------------------------------|
+//! | 1      | number of stanzas, at least one        |
+//! | 1      | kind of the first stanza               |
+//! | 2      | length of the body of the first stanza |
+//! | ...    | body of the first stanza               |
+//! | ...    | more stanzas in the same format        |
+//!
+//! If the [`FLAG_KEY_COMMITMENT`] flag is set, the stanzas are followed by a commitment to the key
+//! of the stream: [`COMMITMENT_LENGTH`] bytes derived from its handle with HKDF-SHA256, which are
+//! checked before anything is decrypted, so that the stream can only be decrypted with one key.
+//!
+//! If the [`FLAG_METADATA`] flag is set, the preamble ends with the [`Metadata`](crate::Metadata)
+//! of the stream, sealed with a key derived from its handle and a random salt:
+//!
+//! | Length | Field                              |
+//! |--------|------------------------------------|
+//! | 2      | length of the salt and sealed box  |
+//! | 32     | salt                               |
+//! | ...    | sealed metadata                    |
+//!
+//! If the [`FLAG_ZSTD`] flag is set, the chunks hold a zstd frame of the plaintext rather than the
+//! plaintext itself. If the [`FLAG_PADDED`] flag is set, the plaintext or its zstd frame is
+//! followed by padding.
+//!
+//! The header and stanzas are passed as associated data to every chunk of the stream, so tampering
+//! with them makes decryption fail.
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{self, Read};
+
+use crate::{error::GaiaError, Algorithm, BUF_SIZE, MAX_CHUNK_SIZE};
+use crate::algorithm::{plaintext_length, TAG_LENGTH};
+use crate::recipient::PassphraseParams;
+
+/// The bytes every Gaia ciphertext starts with.
+pub const MAGIC: [u8; 4] = *b"GAIA";
+
+/// The newest format version this build can read and the one it writes.
+pub const VERSION: u8 = 1;
+
+/// The length of an encoded [`Header`] in bytes.
+pub const HEADER_LENGTH: usize = 12;
+
+/// Set when the header is followed by a list of recipient [`Stanza`]s.
+pub const FLAG_RECIPIENTS: u16 = 1 << 0;
+
+/// Set when the plaintext was compressed with zstd before it was encrypted.
+pub const FLAG_ZSTD: u16 = 1 << 1;
+
+/// Set when the plaintext was padded before it was encrypted.
+pub const FLAG_PADDED: u16 = 1 << 2;
+
+/// Set when the preamble ends with the sealed [`Metadata`](crate::Metadata) of the stream.
+pub const FLAG_METADATA: u16 = 1 << 3;
+
+/// Set when the stanzas are followed by a commitment to the key of the stream.
+pub const FLAG_KEY_COMMITMENT: u16 = 1 << 4;
+
+/// Every flag known to this build.
+const KNOWN_FLAGS: u16 = FLAG_RECIPIENTS | FLAG_ZSTD | FLAG_PADDED | FLAG_METADATA | FLAG_KEY_COMMITMENT;
+
+/// The length of the commitment to the key of a stream.
+pub const COMMITMENT_LENGTH: usize = 32;
+
+/// A decoded header, see the [module documentation](self) for its layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub flags: u16,
+    pub chunk_size: u32,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self { version: VERSION, algorithm: Algorithm::default(), flags: 0, chunk_size: BUF_SIZE as u32 }
+    }
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut bytes = [0u8; HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.algorithm.id();
+        bytes[6..8].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.chunk_size.to_le_bytes());
+        bytes
+    }
+
+    /// Parses and validates a header from the start of `bytes`.
+    ///
+    /// Input that does not start with [`MAGIC`] is rejected as [`HeaderError::NotGaia`] even when it
+    /// is too short to hold a whole header, so that short foreign files are not reported as truncated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HeaderError> {
+        let magic_length = bytes.len().min(MAGIC.len());
+        if bytes[..magic_length] != MAGIC[..magic_length] {
+            return Err(HeaderError::NotGaia);
+        }
+
+        if bytes.len() < HEADER_LENGTH {
+            return Err(HeaderError::Truncated);
+        }
+
+        if bytes[4] != VERSION {
+            return Err(HeaderError::UnsupportedVersion(bytes[4]));
+        }
+
+        let header = Self {
+            version: bytes[4],
+            algorithm: Algorithm::from_id(bytes[5]).ok_or(HeaderError::UnsupportedAlgorithm(bytes[5]))?,
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            chunk_size: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+        };
+
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return Err(HeaderError::UnsupportedFlags(header.flags));
+        }
+
+        if header.chunk_size == 0 || header.chunk_size as usize > MAX_CHUNK_SIZE {
+            return Err(HeaderError::UnsupportedChunkSize(header.chunk_size));
+        }
+
+        Ok(header)
+    }
+}
+
+/// A recipient stanza, holding the key of the stream wrapped for one recipient.
+///
+/// The meaning of the body depends on the kind of the stanza, see [`Recipient`](crate::Recipient).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stanza {
+    pub kind: u8,
+    pub body: Vec<u8>,
+}
+
+impl Stanza {
+    pub(crate) fn encode_all(stanzas: &[Stanza]) -> Vec<u8> {
+        let mut bytes = vec![stanzas.len() as u8];
+        for stanza in stanzas {
+            bytes.push(stanza.kind);
+            bytes.extend_from_slice(&(stanza.body.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&stanza.body);
+        }
+        bytes
+    }
+
+    /// Decodes the stanzas following the header. `bytes` must be complete, as checked with
+    /// [`missing_preamble_bytes`].
+    pub(crate) fn decode_all(bytes: &[u8]) -> Vec<Stanza> {
+        let mut stanzas = Vec::with_capacity(bytes[0] as usize);
+        let mut position = 1;
+        for _ in 0..bytes[0] {
+            let length = u16::from_le_bytes([bytes[position + 1], bytes[position + 2]]) as usize;
+            stanzas.push(Stanza { kind: bytes[position], body: bytes[position + 3..position + 3 + length].to_vec() });
+            position += 3 + length;
+        }
+        stanzas
+    }
+}
+
+/// Returns how many more bytes must be read to complete `preamble`, that is, everything in front
+/// of the first chunk of a stream. Fails as soon as the bytes so far are known to be invalid.
+pub(crate) fn missing_preamble_bytes(preamble: &[u8]) -> Result<usize, HeaderError> {
+    let header = match Header::from_bytes(preamble) {
+        Err(HeaderError::Truncated) => return Ok(HEADER_LENGTH - preamble.len()),
+        header => header?,
+    };
+
+    let mut position = HEADER_LENGTH;
+    if header.flags & FLAG_RECIPIENTS != 0 {
+        let Some(&count) = preamble.get(position) else { return Ok(1) };
+        if count == 0 {
+            return Err(HeaderError::MalformedRecipients);
+        }
+
+        position += 1;
+        for _ in 0..count {
+            let Some(length) = preamble.get(position + 1..position + 3) else {
+                return Ok(position + 3 - preamble.len());
+            };
+            position += 3 + u16::from_le_bytes([length[0], length[1]]) as usize;
+            if preamble.len() < position {
+                return Ok(position - preamble.len());
+            }
+        }
+    }
+
+    if header.flags & FLAG_KEY_COMMITMENT != 0 {
+        position += COMMITMENT_LENGTH;
+        if preamble.len() < position {
+            return Ok(position - preamble.len());
+        }
+    }
+
+    if header.flags & FLAG_METADATA != 0 {
+        let Some(length) = preamble.get(position..position + 2) else {
+            return Ok(position + 2 - preamble.len());
+        };
+        position += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+    Ok(position.saturating_sub(preamble.len()))
+}
+
+/// Returns where the stanzas end in a complete `preamble`, which is where the commitment starts.
+pub(crate) fn stanzas_end(preamble: &[u8]) -> usize {
+    let flags = u16::from_le_bytes([preamble[6], preamble[7]]);
+    if flags & FLAG_RECIPIENTS == 0 {
+        return HEADER_LENGTH;
+    }
+
+    let mut position = HEADER_LENGTH + 1;
+    for _ in 0..preamble[HEADER_LENGTH] {
+        position += 3 + u16::from_le_bytes([preamble[position + 1], preamble[position + 2]]) as usize;
+    }
+    position
+}
+
+/// Returns where the sealed metadata starts in a complete `preamble`, right after the commitment.
+pub(crate) fn metadata_offset(preamble: &[u8]) -> usize {
+    let flags = u16::from_le_bytes([preamble[6], preamble[7]]);
+    match flags & FLAG_KEY_COMMITMENT {
+        0 => stanzas_end(preamble),
+        _ => stanzas_end(preamble) + COMMITMENT_LENGTH,
+    }
+}
+
+/// Returns the length of the preamble at the start of `bytes`, or `None` if it is not complete yet.
+pub(crate) fn preamble_length(bytes: &[u8]) -> Result<Option<usize>, HeaderError> {
+    let mut length = 0;
+    loop {
+        match missing_preamble_bytes(&bytes[..length])? {
+            0 => return Ok(Some(length)),
+            missing if length + missing > bytes.len() => {
+                // Still fails if the incomplete preamble is already known to be invalid
+                missing_preamble_bytes(bytes)?;
+                return Ok(None);
+            }
+            missing => length += missing,
+        }
+    }
+}
+
+/// What can be told about a stream without its key, see [`inspect`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub header: Header,
+    /// Number of recipient stanzas, or 0 without [`FLAG_RECIPIENTS`].
+    pub recipients: usize,
+    /// Length of everything in front of the first chunk.
+    pub preamble_length: u64,
+    /// Chunks of [`chunk_size`](Header::chunk_size) bytes of plaintext in front of the final chunk.
+    pub full_chunks: u64,
+    /// Length of the final chunk with its tag, or `None` if there is not even one.
+    pub final_chunk_length: Option<u64>,
+    /// Length of the plaintext, or of the zstd frame and padding with [`FLAG_ZSTD`] or [`FLAG_PADDED`].
+    pub plaintext_length: u64,
+    /// Bytes after the final chunk that are too few to be a chunk of their own.
+    pub trailing_bytes: u64,
+}
+
+/// Reads all of `input` and tells its [`Layout`] from the preamble and its length, without
+/// decrypting any of it.
+///
+/// Without the key, the final chunk can only be told from its length, so a stream that was cut
+/// short or added to by a whole number of chunks looks intact here. [`verify`](crate::verify) tells
+/// these apart with the key.
+pub fn inspect(mut input: impl Read) -> Result<Layout, GaiaError> {
+    let mut preamble = vec![];
+    loop {
+        let missing = missing_preamble_bytes(&preamble).map_err(GaiaError::InvalidHeader)?;
+        if missing == 0 {
+            break;
+        }
+        if input.by_ref().take(missing as u64).read_to_end(&mut preamble).map_err(GaiaError::ReadingInput)? < missing {
+            return Err(GaiaError::InvalidHeader(HeaderError::Truncated));
+        }
+    }
+
+    let header = Header::from_bytes(&preamble).map_err(GaiaError::InvalidHeader)?;
+    let recipients = match header.flags & FLAG_RECIPIENTS {
+        0 => 0,
+        _ => preamble[HEADER_LENGTH] as usize,
+    };
+
+    // Every chunk is chunk_size bytes of plaintext and a tag, except for the final one, which can be
+    // shorter but is never shorter than a tag
+    let chunks_length = io::copy(&mut input, &mut io::sink()).map_err(GaiaError::ReadingInput)?;
+    let chunk_length = header.chunk_size as u64 + TAG_LENGTH as u64;
+    let (whole_chunks, rest) = (chunks_length / chunk_length, chunks_length % chunk_length);
+    let (full_chunks, final_chunk_length, trailing_bytes) = match (whole_chunks, rest) {
+        (_, rest) if rest >= TAG_LENGTH as u64 => (whole_chunks, Some(rest), 0),
+        (0, rest) => (0, None, rest),
+        (whole_chunks, rest) => (whole_chunks - 1, Some(chunk_length), rest),
+    };
+
+    Ok(Layout {
+        header,
+        recipients,
+        preamble_length: preamble.len() as u64,
+        full_chunks,
+        final_chunk_length,
+        plaintext_length: plaintext_length(chunks_length - trailing_bytes, header.chunk_size as u64),
+        trailing_bytes,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    NotGaia,
+    Truncated,
+    UnsupportedVersion(u8),
+    UnsupportedAlgorithm(u8),
+    UnsupportedFlags(u16),
+    UnsupportedChunkSize(u32),
+    HandleMismatch(Algorithm),
+    MalformedRecipients,
+    NoMatchingIdentity,
+    InvalidMetadata,
+    CommitmentMismatch,
+    ExcessivePassphraseCost(PassphraseParams),
+}
+
+impl Debug for HeaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotGaia => write!(f, "magic bytes do not match"),
+            Self::Truncated => write!(f, "header is shorter than {HEADER_LENGTH} bytes"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+            Self::UnsupportedAlgorithm(algorithm) => write!(f, "unsupported algorithm id {algorithm}"),
+            Self::UnsupportedFlags(flags) => write!(f, "unsupported flags {flags:#06x}"),
+            Self::UnsupportedChunkSize(chunk_size) => write!(f, "unsupported chunk size {chunk_size}"),
+            Self::HandleMismatch(algorithm) => write!(f, "handle does not fit {algorithm}"),
+            Self::MalformedRecipients => write!(f, "malformed recipient stanzas"),
+            Self::NoMatchingIdentity => write!(f, "no identity unwraps the key"),
+            Self::InvalidMetadata => write!(f, "metadata does not open or decode"),
+            Self::CommitmentMismatch => write!(f, "key does not match the commitment"),
+            Self::ExcessivePassphraseCost(params) => write!(f, "passphrase costs {params:?} exceed the limits"),
+        }
+    }
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotGaia => write!(f, "the input is not a Gaia ciphertext"),
+            Self::Truncated => write!(f, "the input ends in the middle of the Gaia header"),
+            Self::UnsupportedVersion(version) => write!(f, "the input uses format version {version}, but only version {VERSION} is supported"),
+            Self::UnsupportedAlgorithm(algorithm) => write!(f, "the input was encrypted with an unknown algorithm (id {algorithm})"),
+            Self::UnsupportedFlags(flags) => write!(f, "the input uses unknown format flags ({flags:#06x})"),
+            Self::UnsupportedChunkSize(chunk_size) => write!(f, "the input uses an unsupported chunk size of {chunk_size} bytes"),
+            Self::HandleMismatch(algorithm) => write!(f, "the input was encrypted with {algorithm}, which the secret is not meant for"),
+            Self::MalformedRecipients => write!(f, "the list of recipients of the input is malformed"),
+            Self::NoMatchingIdentity => write!(f, "none of the given secrets, passphrases or identities can decrypt the input"),
+            Self::InvalidMetadata => write!(f, "the metadata of the input cannot be decrypted or is malformed"),
+            Self::CommitmentMismatch => write!(f, "the input is committed to a different key than the one it would be decrypted with"),
+            Self::ExcessivePassphraseCost(params) => write!(f, "the input asks for {} KiB of memory, {} iterations and {} lanes to derive a key from the passphrase, which is more than is allowed",
+                params.memory_kib, params.iterations, params.parallelism),
+        }
+    }
+}
+
+impl Error for HeaderError {}
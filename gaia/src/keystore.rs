@@ -1,12 +1,19 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
-use aead::KeySizeUser;
-use aead::generic_array::GenericArray;
-use aead::generic_array::sequence::Concat;
-use aead::generic_array::typenum::Unsigned;
 use base64::{DecodeError, Engine};
 use base64::prelude::BASE64_URL_SAFE;
-use crate::{Cipher, Handle};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{Handle, Key, KEY_LENGTH};
+
+/// The prefix of an encoded X25519 public key.
+const PUBLIC_KEY_PREFIX: &str = "gaia-public-";
+
+/// The prefix of an encoded X25519 identity.
+const IDENTITY_PREFIX: &str = "GAIA-SECRET-";
+
+/// The prefix of an encoded [`Key`] for sealing.
+const KEY_PREFIX: &str = "gaia-key-";
 
 pub enum ConversionError {
     Base64EncodingError(DecodeError),
@@ -41,12 +48,94 @@ impl Error for ConversionError {
 }
 
 pub fn to_secret(kh: &Handle) -> Result<String, ConversionError> {
-    Ok(BASE64_URL_SAFE.encode(kh.0.concat(kh.1)))
+    Ok(BASE64_URL_SAFE.encode([kh.key().as_slice(), kh.nonce()].concat()))
 }
 
 
 pub fn from_secret(secret: &str) -> Result<Handle, ConversionError> {
     let data = BASE64_URL_SAFE.decode(secret).map_err(|e| ConversionError::Base64EncodingError(e))?;
-    let (key, nonce) = data.split_at(<Cipher as KeySizeUser>::KeySize::to_usize());
-    Ok((*GenericArray::from_slice(key), *GenericArray::from_slice(nonce)))
-}
\ No newline at end of file
+    let (key, nonce) = data.split_at_checked(KEY_LENGTH).ok_or(ConversionError::KeyEncodingError(aead::Error))?;
+    Handle::new(key.try_into().unwrap(), nonce).ok_or(ConversionError::KeyEncodingError(aead::Error))
+}
+
+pub fn to_public_key(key: &PublicKey) -> String {
+    format!("{PUBLIC_KEY_PREFIX}{}", BASE64_URL_SAFE.encode(key.as_bytes()))
+}
+
+pub fn from_public_key(string: &str) -> Result<PublicKey, ConversionError> {
+    Ok(PublicKey::from(decode_prefixed(string, PUBLIC_KEY_PREFIX)?))
+}
+
+pub fn to_identity(secret: &StaticSecret) -> String {
+    format!("{IDENTITY_PREFIX}{}", BASE64_URL_SAFE.encode(secret.as_bytes()))
+}
+
+pub fn from_identity(string: &str) -> Result<StaticSecret, ConversionError> {
+    Ok(StaticSecret::from(decode_prefixed(string, IDENTITY_PREFIX)?))
+}
+
+pub fn to_key(key: &Key) -> String {
+    format!("{KEY_PREFIX}{}", BASE64_URL_SAFE.encode(key.as_bytes()))
+}
+
+pub fn from_key(string: &str) -> Result<Key, ConversionError> {
+    Ok(Key::new(decode_prefixed(string, KEY_PREFIX)?))
+}
+
+/// Encodes a message from [`seal`](crate::seal) as text.
+pub fn to_sealed(sealed: &[u8]) -> String {
+    BASE64_URL_SAFE.encode(sealed)
+}
+
+pub fn from_sealed(string: &str) -> Result<Vec<u8>, ConversionError> {
+    BASE64_URL_SAFE.decode(string).map_err(ConversionError::Base64EncodingError)
+}
+
+fn decode_prefixed(string: &str, prefix: &str) -> Result<[u8; 32], ConversionError> {
+    let encoded = string.strip_prefix(prefix).ok_or(ConversionError::KeyEncodingError(aead::Error))?;
+    let data = BASE64_URL_SAFE.decode(encoded).map_err(ConversionError::Base64EncodingError)?;
+    data.try_into().map_err(|_| ConversionError::KeyEncodingError(aead::Error))
+}
+
+#[cfg(test)]
+mod tests {
+    use aead::OsRng;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use crate::{generate_handle, open, seal, Algorithm, Handle, Key};
+    use super::{from_identity, from_key, from_public_key, from_sealed, from_secret, to_identity, to_key, to_public_key, to_sealed, to_secret};
+
+    #[test]
+    fn sealed_round_trip() {
+        let key = Key::generate(&mut OsRng);
+        let encoded_key = to_key(&key);
+        assert!(encoded_key.starts_with("gaia-key-"));
+        let key = from_key(&encoded_key).unwrap();
+
+        let sealed = to_sealed(&seal(&key, b"hunter2", b""));
+        assert_eq!(open(&key, &from_sealed(&sealed).unwrap(), b"").unwrap(), b"hunter2");
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for handle in [generate_handle(&mut OsRng), Handle::generate(Algorithm::XChaCha20Poly1305, &mut OsRng)] {
+            assert_eq!(from_secret(&to_secret(&handle).unwrap()).unwrap(), handle);
+        }
+
+        let secret = StaticSecret::random_from_rng(OsRng);
+        assert_eq!(from_identity(&to_identity(&secret)).unwrap().as_bytes(), secret.as_bytes());
+        assert_eq!(from_public_key(&to_public_key(&PublicKey::from(&secret))).unwrap(), PublicKey::from(&secret));
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        let key = to_key(&Key::generate(&mut OsRng));
+        // A key of another kind, a missing prefix, bad base64 and a short key
+        assert!(from_identity(&key).is_err());
+        assert!(from_key(key.trim_start_matches("gaia-key-")).is_err());
+        assert!(from_key("gaia-key-!!!").is_err());
+        assert!(from_key("gaia-key-AAAA").is_err());
+        assert!(from_sealed("not base64!").is_err());
+        assert!(from_secret("AAAA").is_err());
+    }
+}
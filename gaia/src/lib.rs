@@ -1,7 +1,10 @@
 #![feature(let_chains)]
-#![feature(buf_read_has_data_left)]
 //! A library for encrypting and decrypting data using the STREAM-LE-31 construction
-//! with the AES-256-GCM-SIV cipher.
+//! with AES-256-GCM-SIV, ChaCha20-Poly1305 or XChaCha20-Poly1305, see [`Algorithm`].
+//!
+//! Every ciphertext starts with a versioned [header](header::Header). Ciphertexts written by
+//! gaia 0.1 have no header and cannot be decrypted by this version, which rejects them with
+//! [`HeaderError::NotGaia`](header::HeaderError::NotGaia).
 //! 
 //! # Examples
 //! 
@@ -18,7 +21,7 @@
 //!    let handle = encrypt(input.as_bytes(), &mut encrypted)?;
 //! 
 //!    let mut decrypted = Vec::new();
-//!    decrypt(&encrypted, &mut decrypted, &handle)?;
+//!    decrypt(encrypted.as_slice(), &handle, &mut decrypted)?;
 //!    assert_eq!(input.as_bytes(), decrypted.as_slice());
 //!    Ok(())
 //! }
@@ -27,10 +30,13 @@
 //! ## Asynchronous, `encrypt_async` and `decrypt_async` functions
 //! 
 //! ```rust
+//! # #[cfg(feature = "tokio")]
 //! use tokio::io::{AsyncRead, AsyncWrite};
 //! 
+//! # #[cfg(feature = "tokio")]
 //! use gaia::{encrypt_async, decrypt_async, generate_handle, error::GaiaError};
 //! 
+//! # #[cfg(feature = "tokio")]
 //! #[tokio::main]
 //! async fn main() -> Result<(), GaiaError> {
 //!     let input = "Hello, world!";
@@ -38,10 +44,12 @@
 //!     let handle = encrypt_async(input.as_bytes(), &mut encrypted).await?;
 //!     
 //!     let mut decrypted = Vec::new();
-//!     decrypt_async(&encrypted, &handle, &mut decrypted).await?;
+//!     decrypt_async(encrypted.as_slice(), &handle, &mut decrypted).await?;
 //!     assert_eq!(input.as_bytes(), decrypted.as_slice());
 //!     Ok(())
 //! }
+//! # #[cfg(not(feature = "tokio"))]
+//! # fn main() {}
 //! ```
 //! 
 //! ## Synchronous, `EncryptingReader` and `DecryptingReader` structs
@@ -50,15 +58,15 @@
 //! use std::io::{Read, Write};
 //! use rand_core::OsRng;
 //!
-//! use gaia::{EncryptingReader, DecryptingReader, generate_handle, error::GaiaError};
+//! use gaia::{EncryptingReader, DecryptingReader, generate_handle};
 //!
-//! fn main() -> Result<(), GaiaError> {
+//! fn main() -> std::io::Result<()> {
 //!     let input = "Hello, world!";
 //!     let (mut reader, handle) = EncryptingReader::new_with_os_rng(input.as_bytes());
 //!     let mut encrypted = Vec::new();
 //!     reader.read_to_end(&mut encrypted)?;
 //!     
-//!     let mut reader = DecryptingReader::new(&encrypted, &handle);
+//!     let mut reader = DecryptingReader::new(encrypted.as_slice(), &handle);
 //!     let mut decrypted = Vec::new();
 //!     reader.read_to_end(&mut decrypted)?;
 //! 
@@ -69,76 +77,153 @@
 //! 
 //! ## Asynchronous, `AsyncEncryptingReader` and `AsyncDecryptingReader` structs
 //! ```rust
+//! # #[cfg(feature = "tokio")]
 //! use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 //!
-//! use gaia::{AsyncEncryptingReader, AsyncDecryptingReader, generate_handle, error::GaiaError};
+//! # #[cfg(feature = "tokio")]
+//! use gaia::{AsyncEncryptingReader, AsyncDecryptingReader, generate_handle};
 //!
+//! # #[cfg(feature = "tokio")]
 //! #[tokio::main]
-//! async fn main() -> Result<(), GaiaError> {
+//! async fn main() -> std::io::Result<()> {
 //!     let input = "Hello, world!";
 //!     let (mut reader, handle) = AsyncEncryptingReader::new_with_os_rng(input.as_bytes());
 //!     let mut encrypted = Vec::new();
 //!     reader.read_to_end(&mut encrypted).await?;
 //!     
-//!     let mut reader = AsyncDecryptingReader::new(&encrypted, &handle);
+//!     let mut reader = AsyncDecryptingReader::new(encrypted.as_slice(), &handle);
 //!     let mut decrypted = Vec::new();
 //!     reader.read_to_end(&mut decrypted).await?;
 //! 
 //!     assert_eq!(input.as_bytes(), decrypted.as_slice());
 //!     Ok(())
 //! }
+//! # #[cfg(not(feature = "tokio"))]
+//! # fn main() {}
 //! ```
 
-use std::ops::Sub;
+use std::fmt::{Debug, Formatter};
 
-use aead::{AeadCore, stream::{
-    Decryptor, Encryptor,
-    StreamLE31, StreamPrimitive
-}, stream};
-use aead::generic_array::GenericArray;
 use aead::rand_core::{CryptoRng, RngCore};
 
-use crypto_common::Key;
-
-/// The buffer size to which to split the input data.
-/// This must stay consistent between encryption and decryption, but in general
-/// this is an implementation detail and should not be exposed to the user.
+/// The default buffer size to which to split the input data.
+/// This must stay consistent between encryption and decryption, which is why it
+/// is recorded in the [header](header::Header) of every ciphertext.
 const BUF_SIZE: usize = 16384;
 
-/// The type of cipher used for encryption and decryption, AES-256-GCM-SIV.
-pub type Cipher = aes_gcm_siv::Aes256GcmSiv;
+/// The largest chunk size that can be used for encryption or will be accepted for decryption.
+pub const MAX_CHUNK_SIZE: usize = 1 << 24;
 
-/// The AEAD construction used for encryption and decryption, STREAM-LE-31.
-pub type Stream = StreamLE31<Cipher>;
+/// The length of the key in a [`Handle`], which is the same for every algorithm.
+pub const KEY_LENGTH: usize = 32;
 
-/// A pair of a key and nonce, required for encryption and decryption.
-pub type Handle = (Key<Cipher>, stream::Nonce<Cipher, Stream>);
+mod algorithm;
+mod buffer;
+#[cfg(feature = "compression")]
+mod compression;
+mod encoding;
+pub mod error;
+pub mod header;
+mod metadata;
+mod options;
+mod padding;
+mod recipient;
+mod seal;
+mod spool;
 
-// I would document these types, but I forget how STREAM works.
-type StreamNonceOverhead = <Stream as StreamPrimitive<Cipher>>::NonceOverhead;
-type StreamNonceLength = <<Cipher as AeadCore>::NonceSize as Sub<StreamNonceOverhead>>::Output;
-type StreamTagLength = <Cipher as AeadCore>::TagSize;
+pub use algorithm::Algorithm;
+#[cfg(feature = "compression")]
+pub use compression::Compression;
+pub use metadata::Metadata;
+pub use padding::Padding;
+pub use options::{DecryptOptions, EncryptOptions};
+pub use recipient::{Identity, PassphraseParams, Recipient, MAX_RECIPIENTS};
+pub use seal::{seal, open, Key, SEAL_OVERHEAD};
+pub use spool::Spool;
 
-pub mod error;
+/// The X25519 implementation used for [`Recipient::X25519`] and [`Identity::X25519`].
+pub use x25519_dalek;
 
 #[cfg(feature = "base64")]
 pub mod keystore;
 
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod async_crypt;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub use async_crypt::{AsyncEncryptingReader, AsyncDecryptingReader};
 #[cfg(feature = "tokio")]
 mod tokio_crypt;
 #[cfg(feature = "tokio")]
-pub use tokio_crypt::{encrypt_async, decrypt_async, AsyncEncryptingReader, AsyncDecryptingReader};
+pub use tokio_crypt::{encrypt_async, encrypt_async_with_options, encrypt_async_for, decrypt_async, decrypt_async_with_identity, decrypt_async_with_identities, decrypt_async_with_options, verify_async, verify_async_with_options, AsyncEncryptingWriter, AsyncDecryptingWriter};
+#[cfg(feature = "futures-io")]
+pub mod futures;
+#[cfg(any(feature = "stream", feature = "codec"))]
+mod chunker;
+#[cfg(feature = "stream")]
+mod stream;
+#[cfg(feature = "stream")]
+pub use stream::{encrypt_stream, encrypt_stream_with_options, decrypt_stream, decrypt_stream_with_options};
+#[cfg(feature = "codec")]
+mod codec;
+#[cfg(feature = "codec")]
+pub use codec::{EncryptingCodec, DecryptingCodec};
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "parallel")]
+pub use parallel::{encrypt_parallel, decrypt_parallel};
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::encrypt_file;
 mod std_crypt;
 
-pub use std_crypt::{encrypt, decrypt, EncryptingReader, DecryptingReader};
+pub use std_crypt::{encrypt, encrypt_with_options, encrypt_for, decrypt, decrypt_with_identity, decrypt_with_identities, decrypt_with_options, verify, verify_with_options, EncryptingReader, DecryptingReader, EncryptingWriter, DecryptingWriter};
 
-/// Generates a Handle using the provided cryptographically secure random number generator.
-/// Both the key and nonce are filled with random bytes.
-pub fn generate_handle(rng: &mut (impl CryptoRng + RngCore)) -> Handle {
-    let mut key = Key::<Cipher>::default();
-    rng.fill_bytes(&mut key);
+/// A pair of a key and nonce, required for encryption and decryption.
+///
+/// The length of the nonce depends on the [`Algorithm`] the handle is used with,
+/// see [`Algorithm::nonce_length`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct Handle {
+    key: [u8; KEY_LENGTH],
+    nonce: Vec<u8>,
+}
 
-    let mut stream_nonce = GenericArray::<u8, StreamNonceLength>::default();
-    rng.fill_bytes(&mut stream_nonce);
-    (key, stream_nonce)
+impl Handle {
+    /// Creates a handle from its parts, or returns `None` if the nonce does not fit any algorithm.
+    pub fn new(key: [u8; KEY_LENGTH], nonce: &[u8]) -> Option<Self> {
+        Algorithm::ALL.iter().any(|algorithm| algorithm.nonce_length() == nonce.len())
+            .then(|| Self { key, nonce: nonce.to_vec() })
+    }
+
+    /// Generates a handle for the given algorithm using the provided cryptographically secure
+    /// random number generator. Both the key and nonce are filled with random bytes.
+    pub fn generate(algorithm: Algorithm, rng: &mut (impl CryptoRng + RngCore)) -> Self {
+        let mut key = [0u8; KEY_LENGTH];
+        rng.fill_bytes(&mut key);
+
+        let mut nonce = vec![0u8; algorithm.nonce_length()];
+        rng.fill_bytes(&mut nonce);
+        Self { key, nonce }
+    }
+
+    pub fn key(&self) -> &[u8; KEY_LENGTH] {
+        &self.key
+    }
+
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+}
+
+impl Debug for Handle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle").finish_non_exhaustive()
+    }
+}
+
+/// Generates a Handle for the default [`Algorithm`] using the provided cryptographically secure
+/// random number generator. Both the key and nonce are filled with random bytes.
+pub fn generate_handle(rng: &mut (impl CryptoRng + RngCore)) -> Handle {
+    Handle::generate(Algorithm::default(), rng)
 }
@@ -0,0 +1,299 @@
+//! Describes the file a stream was encrypted from, stored encrypted in the preamble, see [`Metadata`].
+//!
+//! The metadata is encoded as a list of fields, each of which is a kind byte, a 2-byte little-endian
+//! length and the value. Fields of unknown kinds are skipped, so that newer fields can be added
+//! without breaking older readers. The encoded metadata is sealed with ChaCha20-Poly1305 under a key
+//! derived from the handle of the stream and a random salt, which is stored in front of it, with
+//! everything in front of it in the preamble as associated data.
+
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aead::{Aead, KeyInit, OsRng, Payload};
+use aead::generic_array::GenericArray;
+use aead::rand_core::RngCore;
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::{Handle, KEY_LENGTH};
+use crate::algorithm::TAG_LENGTH;
+use crate::header::HeaderError;
+
+/// Domain separation for the key that seals the metadata.
+const METADATA_INFO: &[u8] = b"gaia metadata";
+
+/// The length of the salt in front of the sealed metadata. As the same handle can be given to any
+/// number of streams, every stream derives a fresh key from its own salt.
+const SALT_LENGTH: usize = 32;
+
+const NAME_FIELD: u8 = 1;
+const MODIFIED_FIELD: u8 = 2;
+const PERMISSIONS_FIELD: u8 = 3;
+const MIME_TYPE_FIELD: u8 = 4;
+
+/// The permission bits that are stored and restored. Others, like setuid, are left out so that a
+/// stream cannot make the files it is decrypted into more powerful.
+const PERMISSION_BITS: u32 = 0o777;
+
+/// What is known about the file a stream was encrypted from, see [`EncryptOptions::metadata`](crate::EncryptOptions::metadata).
+///
+/// Every field is optional. The metadata is authenticated and encrypted, but its length is not
+/// hidden.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+/// use gaia::{DecryptingReader, EncryptOptions, EncryptingReader, Metadata, generate_handle};
+///
+/// let metadata = Metadata::new().name("ILOVEYOU.txt").mime_type("text/plain");
+/// let options = EncryptOptions::new().metadata(metadata.clone());
+/// let handle = generate_handle(&mut rand_core::OsRng);
+///
+/// let mut encrypted = Vec::new();
+/// EncryptingReader::with_options("Hello, world!".as_bytes(), &handle, &options).read_to_end(&mut encrypted).unwrap();
+///
+/// let mut reader = DecryptingReader::new(encrypted.as_slice(), &handle);
+/// assert_eq!(reader.metadata().unwrap(), Some(&metadata));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The name of the file, without any directories.
+    pub name: Option<String>,
+    /// When the file was last modified.
+    pub modified: Option<SystemTime>,
+    /// The Unix permission bits of the file, like `0o644`.
+    pub permissions: Option<u32>,
+    /// The MIME type of the contents of the file, like `text/plain`.
+    pub mime_type: Option<String>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the name, modification time and permission bits of the file at `path`.
+    ///
+    /// Names that are not valid UTF-8 are left out, and so are permission bits on platforms other than Unix.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let metadata = path.metadata()?;
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & PERMISSION_BITS)
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        Ok(Self {
+            name: path.file_name().and_then(|name| name.to_str()).map(str::to_string),
+            modified: metadata.modified().ok(),
+            permissions,
+            mime_type: None,
+        })
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+
+    pub fn permissions(mut self, permissions: u32) -> Self {
+        self.permissions = Some(permissions & PERMISSION_BITS);
+        self
+    }
+
+    pub fn mime_type(mut self, mime_type: impl Into<String>) -> Self {
+        self.mime_type = Some(mime_type.into());
+        self
+    }
+
+    /// Returns the name if it is safe to create a file with it in a directory, that is, if it is a
+    /// single normal path component on every platform.
+    ///
+    /// The name comes from whoever encrypted the stream, so it must not be trusted to stay inside
+    /// the directory it is restored into.
+    pub fn safe_name(&self) -> Option<&str> {
+        let name = self.name.as_deref()?;
+        if name.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(component)), None) if component == name => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Sets the modification time and permission bits of `file` to the ones in the metadata,
+    /// if there are any. Permission bits are only set on Unix.
+    pub fn apply(&self, file: &File) -> io::Result<()> {
+        if let Some(modified) = self.modified {
+            file.set_modified(modified)?;
+        }
+        #[cfg(unix)]
+        if let Some(permissions) = self.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(permissions & PERMISSION_BITS))?;
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut fields: Vec<(u8, Vec<u8>)> = vec![];
+        if let Some(ref name) = self.name {
+            fields.push((NAME_FIELD, name.as_bytes().to_vec()));
+        }
+        if let Some(modified) = self.modified {
+            fields.push((MODIFIED_FIELD, encode_time(modified).to_vec()));
+        }
+        if let Some(permissions) = self.permissions {
+            fields.push((PERMISSIONS_FIELD, permissions.to_le_bytes().to_vec()));
+        }
+        if let Some(ref mime_type) = self.mime_type {
+            fields.push((MIME_TYPE_FIELD, mime_type.as_bytes().to_vec()));
+        }
+
+        let mut bytes = vec![];
+        for (kind, value) in fields {
+            bytes.push(kind);
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&value);
+        }
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<Self> {
+        let mut metadata = Self::new();
+        while let Some((&kind, rest)) = bytes.split_first() {
+            let (length, rest) = rest.split_first_chunk::<2>()?;
+            let (value, rest) = rest.split_at_checked(u16::from_le_bytes(*length) as usize)?;
+            match kind {
+                NAME_FIELD => metadata.name = Some(String::from_utf8(value.to_vec()).ok()?),
+                MODIFIED_FIELD => metadata.modified = Some(decode_time(value.try_into().ok()?)?),
+                PERMISSIONS_FIELD => metadata.permissions = Some(u32::from_le_bytes(value.try_into().ok()?) & PERMISSION_BITS),
+                MIME_TYPE_FIELD => metadata.mime_type = Some(String::from_utf8(value.to_vec()).ok()?),
+                _ => {}
+            }
+            bytes = rest;
+        }
+        Some(metadata)
+    }
+}
+
+/// Encodes a time as signed seconds and nanoseconds since the Unix epoch.
+fn encode_time(time: SystemTime) -> [u8; 12] {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => match e.duration() {
+            before if before.subsec_nanos() == 0 => (-(before.as_secs() as i64), 0),
+            before => (-(before.as_secs() as i64) - 1, 1_000_000_000 - before.subsec_nanos()),
+        },
+    };
+    let mut bytes = [0u8; 12];
+    bytes[0..8].copy_from_slice(&seconds.to_le_bytes());
+    bytes[8..12].copy_from_slice(&nanos.to_le_bytes());
+    bytes
+}
+
+fn decode_time(bytes: [u8; 12]) -> Option<SystemTime> {
+    let seconds = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let nanos = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let time = match seconds {
+        0.. => UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))?,
+        _ => UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))?,
+    };
+    time.checked_add(Duration::from_nanos(nanos as u64))
+}
+
+/// Derives the key sealing the metadata of a stream from its handle and the salt of the metadata.
+fn derive_metadata_key(handle: &Handle, salt: &[u8]) -> [u8; KEY_LENGTH] {
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(salt), &[handle.key().as_slice(), handle.nonce()].concat())
+        .expand(METADATA_INFO, &mut key).unwrap();
+    key
+}
+
+/// Encodes and seals `metadata` for the stream of `handle`, binding it to `preamble`, which is
+/// everything in front of it, and returns it behind its salt. Fails if the result does not fit its
+/// 2-byte length.
+pub(crate) fn seal_metadata(handle: &Handle, metadata: &Metadata, preamble: &[u8]) -> io::Result<Vec<u8>> {
+    let encoded = metadata.encode();
+    if SALT_LENGTH + encoded.len() + TAG_LENGTH > u16::MAX as usize {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "the metadata is too long"));
+    }
+
+    // The key is only ever used once, as the salt is fresh, so the nonce can be fixed
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let sealed = ChaCha20Poly1305::new(GenericArray::from_slice(&derive_metadata_key(handle, &salt)))
+        .encrypt(&Default::default(), Payload { msg: &encoded, aad: preamble })
+        .expect("sealing the metadata cannot fail");
+    Ok([salt.as_slice(), &sealed].concat())
+}
+
+/// Opens metadata sealed with [`seal_metadata`].
+pub(crate) fn open_metadata(handle: &Handle, sealed: &[u8], preamble: &[u8]) -> Result<Metadata, HeaderError> {
+    let (salt, sealed) = sealed.split_at_checked(SALT_LENGTH).ok_or(HeaderError::InvalidMetadata)?;
+    let encoded = ChaCha20Poly1305::new(GenericArray::from_slice(&derive_metadata_key(handle, salt)))
+        .decrypt(&Default::default(), Payload { msg: sealed, aad: preamble })
+        .map_err(|_| HeaderError::InvalidMetadata)?;
+    Metadata::decode(&encoded).ok_or(HeaderError::InvalidMetadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{generate_handle, DecryptingReader, EncryptOptions, EncryptingReader};
+    use crate::header::{inspect, metadata_offset, HeaderError};
+    use super::{open_metadata, seal_metadata, Metadata};
+
+    fn metadata() -> Metadata {
+        Metadata::new().name("ILOVEYOU.txt").modified(UNIX_EPOCH + Duration::new(1_000_000_000, 5)).permissions(0o644).mime_type("text/plain")
+    }
+
+    #[test]
+    fn sealed_metadata_differs_under_the_same_handle() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let (first, second) = (seal_metadata(&handle, &metadata(), b"preamble").unwrap(), seal_metadata(&handle, &metadata(), b"preamble").unwrap());
+        assert_ne!(first, second);
+        for sealed in [first, second] {
+            assert_eq!(open_metadata(&handle, &sealed, b"preamble").unwrap(), metadata());
+            assert!(matches!(open_metadata(&handle, &sealed, b"another preamble"), Err(HeaderError::InvalidMetadata)));
+        }
+        assert!(matches!(open_metadata(&handle, &[0; 16], b"preamble"), Err(HeaderError::InvalidMetadata)));
+    }
+
+    #[test]
+    fn streams_with_the_same_handle_seal_their_metadata_apart() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let options = EncryptOptions::new().metadata(metadata());
+        let streams: Vec<Vec<u8>> = (0..2).map(|_| {
+            let mut encrypted = vec![];
+            EncryptingReader::with_options(b"Hello, world!".as_slice(), &handle, &options).read_to_end(&mut encrypted).unwrap();
+            encrypted
+        }).collect();
+
+        let (start, end) = (metadata_offset(&streams[0]) + 2, inspect(streams[0].as_slice()).unwrap().preamble_length as usize);
+        assert_ne!(streams[0][start..end], streams[1][start..end]);
+        for stream in &streams {
+            let mut reader = DecryptingReader::new(stream.as_slice(), &handle);
+            assert_eq!(reader.metadata().unwrap(), Some(&metadata()));
+        }
+    }
+}
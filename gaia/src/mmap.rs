@@ -0,0 +1,166 @@
+//! Encrypts files by memory mapping them, without copying them through readers and writers.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+use aead::OsRng;
+use memmap2::{Mmap, MmapMut};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::{error::GaiaError, EncryptOptions, Handle, Padding};
+use crate::algorithm::{ChunkCipher, TAG_LENGTH};
+use crate::buffer::SliceBuffer;
+use crate::padding::MARKER;
+use crate::recipient::write_preamble;
+
+/// Encrypts the file at `input` into the file at `output`, which is created or replaced.
+///
+/// Both files are memory mapped, and every chunk is encrypted straight from the input into its
+/// place in the output. With the `parallel` feature, the chunks are encrypted on the rayon thread
+/// pool. The output is the same as that of [`encrypt_with_options`](crate::encrypt_with_options).
+///
+/// Compressed output cannot be laid out before it has been compressed, so when the options
+/// [compress](EncryptOptions::compression) the plaintext, the files are read and written as
+/// streams instead. [Padding](EncryptOptions::padding) on its own is laid out like the rest of
+/// the plaintext.
+///
+/// The input must not be changed while it is being encrypted.
+pub fn encrypt_file(input: impl AsRef<Path>, output: impl AsRef<Path>, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let input_file = File::open(&input).map_err(GaiaError::ReadingInput)?;
+    if is_same_file(input.as_ref(), output.as_ref()).map_err(GaiaError::WritingOutput)? {
+        return Err(GaiaError::WritingOutput(io::Error::new(ErrorKind::InvalidInput, "the output is the input file")));
+    }
+    if options.compresses() {
+        return encrypt_streaming(input_file, output, options);
+    }
+
+    // Mapping an empty file fails on some platforms
+    let input_length = input_file.metadata().map_err(GaiaError::ReadingInput)?.len();
+    let input_map = match input_length {
+        0 => None,
+        // SAFETY: The input is only read, and callers are told not to change it while it is being read
+        _ => Some(unsafe { Mmap::map(&input_file) }.map_err(GaiaError::ReadingInput)?),
+    };
+    let plaintext = input_map.as_deref().unwrap_or_default();
+    let padded_length = match options.padding {
+        Padding::None => plaintext.len(),
+        padding => padding.padded_length(plaintext.len() as u64 + 1) as usize,
+    };
+
+    let handle = Handle::generate(options.algorithm, &mut OsRng);
+    let preamble = write_preamble(&handle, options).map_err(GaiaError::WritingOutput)?;
+    let chunk_count = padded_length.div_ceil(options.chunk_size).max(1);
+    let output_length = preamble.len() + padded_length + chunk_count * TAG_LENGTH;
+
+    let output_file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&output)
+        .map_err(GaiaError::WritingOutput)?;
+    output_file.set_len(output_length as u64).map_err(GaiaError::WritingOutput)?;
+    // SAFETY: The output was just created or truncated, and is only written through this map
+    let mut output_map = unsafe { MmapMut::map_mut(&output_file) }.map_err(GaiaError::WritingOutput)?;
+
+    let (output_preamble, chunks) = output_map.split_at_mut(preamble.len());
+    output_preamble.copy_from_slice(&preamble);
+
+    let cipher = ChunkCipher::new(options.algorithm, &handle).expect("the handle was generated for the algorithm");
+    let associated_data = [preamble.as_slice(), &options.associated_data].concat();
+    let encrypt_chunk = |(index, slot): (usize, &mut [u8])| {
+        // The padding is already zeroed by set_len, apart from the marker in front of it
+        let start = index * options.chunk_size;
+        let length = slot.len() - TAG_LENGTH;
+        let chunk = &plaintext[start.min(plaintext.len())..(start + length).min(plaintext.len())];
+        slot[..chunk.len()].copy_from_slice(chunk);
+        if options.padding != Padding::None && (start..start + length).contains(&plaintext.len()) {
+            slot[plaintext.len() - start] = MARKER;
+        }
+
+        let position = u32::try_from(index).map_err(|_| aead::Error)?;
+        cipher.encrypt_in_place(position, index == chunk_count - 1, &associated_data, &mut SliceBuffer::new(slot, length))
+    };
+
+    // Every slot holds a chunk and its tag, even the empty last chunk of an empty input
+    #[cfg(feature = "parallel")]
+    let result = chunks.par_chunks_mut(options.chunk_size + TAG_LENGTH).enumerate().try_for_each(encrypt_chunk);
+    #[cfg(not(feature = "parallel"))]
+    let result = chunks.chunks_mut(options.chunk_size + TAG_LENGTH).enumerate().try_for_each(encrypt_chunk);
+    result.map_err(GaiaError::Encrypting)?;
+
+    output_map.flush().map_err(GaiaError::WritingOutput)?;
+    Ok(handle)
+}
+
+/// Encrypts the opened input into the file at `output` without memory mapping either of them.
+fn encrypt_streaming(input: File, output: impl AsRef<Path>, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let mut output = BufWriter::new(File::create(output).map_err(GaiaError::WritingOutput)?);
+    #[cfg(feature = "parallel")]
+    let handle = crate::encrypt_parallel(input, &mut output, options)?;
+    #[cfg(not(feature = "parallel"))]
+    let handle = crate::encrypt_with_options(input, &mut output, options)?;
+    output.flush().map_err(GaiaError::WritingOutput)?;
+    Ok(handle)
+}
+
+/// Whether `output` exists and is the same file as `input`, which would be truncated before it is read.
+///
+/// Files are compared by device and inode, or their equivalent, so that a hard link or another
+/// mount of the input is caught as well as another path to it.
+fn is_same_file(input: &Path, output: &Path) -> io::Result<bool> {
+    match same_file::is_same_file(input, output) {
+        Err(e) if e.kind() == ErrorKind::NotFound && !output.exists() => Ok(false),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use crate::{encrypt_file, EncryptOptions, EncryptingReader, Padding};
+    use crate::error::GaiaError;
+
+    /// A file in the temporary directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("gaia-mmap-test-{}-{name}", std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn output_is_identical_to_streaming() {
+        let (input, output) = (TempFile::new("identical.in"), TempFile::new("identical.out"));
+        for padding in Padding::ALL {
+            for length in [0, 1, 63, 64, 65, 1000] {
+                let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+                fs::write(&input.0, &plaintext).unwrap();
+                let options = EncryptOptions::new().chunk_size(64).padding(padding).key_commitment(true);
+                let handle = encrypt_file(&input.0, &output.0, &options).unwrap();
+
+                let mut streamed = vec![];
+                EncryptingReader::with_options(plaintext.as_slice(), &handle, &options).read_to_end(&mut streamed).unwrap();
+                assert_eq!(fs::read(&output.0).unwrap(), streamed, "{length} bytes padded with {padding}");
+            }
+        }
+    }
+
+    #[test]
+    fn output_must_not_be_the_input() {
+        let (input, link) = (TempFile::new("same"), TempFile::new("same.link"));
+        fs::write(&input.0, b"Hello, world!").unwrap();
+        fs::hard_link(&input.0, &link.0).unwrap();
+        for output in [&input.0, &link.0] {
+            assert!(matches!(encrypt_file(&input.0, output, &EncryptOptions::new()), Err(GaiaError::WritingOutput(_))));
+            assert_eq!(fs::read(&input.0).unwrap(), b"Hello, world!");
+        }
+    }
+}
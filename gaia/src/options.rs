@@ -0,0 +1,269 @@
+use std::io::{self, ErrorKind};
+
+use crate::{Algorithm, Identity, Metadata, Recipient, BUF_SIZE, MAX_CHUNK_SIZE};
+use crate::algorithm::{ChunkCipher, TAG_LENGTH};
+#[cfg(feature = "compression")]
+use crate::compression::Compression;
+use crate::encoding::PlaintextDecoder;
+use crate::header::{metadata_offset, HeaderError, FLAG_METADATA, FLAG_PADDED, FLAG_ZSTD};
+use crate::metadata::open_metadata;
+use crate::padding::Padding;
+use crate::recipient::open_preamble;
+
+/// Options for encrypting a stream, built up by chaining setters onto [`EncryptOptions::new`].
+///
+/// Everything set here except the [associated data](EncryptOptions::associated_data) is recorded
+/// in the header of the ciphertext, so decryption does not need to be told about it.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+/// use gaia::{EncryptOptions, EncryptingReader, DecryptingReader, generate_handle};
+///
+/// let options = EncryptOptions::new().chunk_size(1 << 20);
+/// let handle = generate_handle(&mut rand_core::OsRng);
+///
+/// let mut encrypted = Vec::new();
+/// EncryptingReader::with_options("Hello, world!".as_bytes(), &handle, &options).read_to_end(&mut encrypted).unwrap();
+///
+/// let mut decrypted = Vec::new();
+/// DecryptingReader::new(encrypted.as_slice(), &handle).read_to_end(&mut decrypted).unwrap();
+/// assert_eq!(decrypted, b"Hello, world!");
+/// ```
+#[derive(Clone, Debug)]
+pub struct EncryptOptions {
+    pub(crate) chunk_size: usize,
+    pub(crate) algorithm: Algorithm,
+    pub(crate) recipients: Vec<Recipient>,
+    pub(crate) associated_data: Vec<u8>,
+    #[cfg(feature = "compression")]
+    pub(crate) compression: Compression,
+    pub(crate) padding: Padding,
+    pub(crate) metadata: Option<Metadata>,
+    pub(crate) key_commitment: bool,
+}
+
+impl Default for EncryptOptions {
+    fn default() -> Self {
+        Self { chunk_size: BUF_SIZE, algorithm: Algorithm::default(), recipients: vec![], associated_data: vec![],
+            #[cfg(feature = "compression")]
+            compression: Compression::None,
+            padding: Padding::None, metadata: None, key_commitment: false }
+    }
+}
+
+impl EncryptOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of plaintext bytes in each chunk of the stream, 16 KiB by default.
+    ///
+    /// Every chunk carries a 16-byte authentication tag, so larger chunks waste less space
+    /// and time on large inputs, while smaller chunks need less memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero or larger than [`MAX_CHUNK_SIZE`].
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!((1..=MAX_CHUNK_SIZE).contains(&chunk_size), "chunk size must be between 1 and {MAX_CHUNK_SIZE} bytes");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the algorithm to encrypt with, AES-256-GCM-SIV by default.
+    ///
+    /// Handles are specific to an algorithm, so the handle used for encryption must have been
+    /// generated with [`Handle::generate`](crate::Handle::generate) for the same algorithm.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Encrypts the stream for another recipient, who can then decrypt it with the matching
+    /// [`Identity`](crate::Identity) as well as with the handle.
+    ///
+    /// A stream can have up to [`MAX_RECIPIENTS`](crate::MAX_RECIPIENTS) recipients, and encrypting
+    /// fails if there are more.
+    pub fn recipient(mut self, recipient: Recipient) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// Encrypts the stream for each of the recipients, as with [`EncryptOptions::recipient`].
+    pub fn recipients(mut self, recipients: impl IntoIterator<Item = Recipient>) -> Self {
+        self.recipients.extend(recipients);
+        self
+    }
+
+    /// Binds the stream to some context, like a file name or the key of an object in a store.
+    ///
+    /// The associated data is authenticated with every chunk but not stored in the ciphertext, so
+    /// decryption must be given the same associated data with [`DecryptOptions::associated_data`]
+    /// or it fails.
+    pub fn associated_data(mut self, associated_data: impl AsRef<[u8]>) -> Self {
+        self.associated_data = associated_data.as_ref().to_vec();
+        self
+    }
+
+    /// Compresses the plaintext before it is encrypted, which none is by default.
+    ///
+    /// Ciphertext looks random and cannot be compressed afterwards, so this is the only place
+    /// compression helps. Leave it off for input that is already compressed, like images, video
+    /// and archives, where it only costs time.
+    ///
+    /// The length of compressed ciphertext depends on the contents of the plaintext. If an
+    /// attacker can both choose part of the plaintext and see the length of the ciphertext, they
+    /// can use this to guess the rest of the plaintext bit by bit, as in the CRIME and BREACH
+    /// attacks. Do not compress plaintext that mixes secrets with data from others.
+    ///
+    /// Compressed plaintext cannot be seeked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the zstd level is outside of [`zstd::compression_level_range`].
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        if let Compression::Zstd(level) = compression {
+            let range = zstd::compression_level_range();
+            assert!(range.contains(&level), "zstd levels must be between {} and {}", range.start(), range.end());
+        }
+        self.compression = compression;
+        self
+    }
+
+    /// Whether the plaintext is compressed, which it never is without the `compression` feature.
+    #[cfg(feature = "compression")]
+    pub(crate) fn compresses(&self) -> bool {
+        self.compression != Compression::None
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub(crate) fn compresses(&self) -> bool {
+        false
+    }
+
+    /// Pads the plaintext so that the length of the ciphertext only gives away roughly how long the
+    /// plaintext is, which it does not by default.
+    ///
+    /// Without padding, the ciphertext is exactly as long as the plaintext plus the header and
+    /// a tag for every chunk, so files can be told apart by their length alone. The padding is
+    /// authenticated along with the plaintext and stripped off again when decrypting. Compressed
+    /// plaintext is padded after it has been compressed. Padded plaintext cannot be seeked.
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Stores metadata about the file the stream is encrypted from, like its name, which none is
+    /// by default.
+    ///
+    /// The metadata is sealed in the preamble with a key derived from the handle, so only those
+    /// who can decrypt the stream can read it, from [`DecryptingReader::metadata`](crate::DecryptingReader::metadata).
+    /// Encrypting fails if it takes up more than 64 KiB.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Commits the stream to its key, which it is not by default.
+    ///
+    /// The algorithms are not key-committing, so a ciphertext can be crafted that decrypts to
+    /// different plaintexts under different keys. With a commitment, which adds 32 bytes to the
+    /// preamble, decryption fails with [`HeaderError::CommitmentMismatch`](crate::header::HeaderError::CommitmentMismatch) before anything is
+    /// decrypted unless it is given the one key that the stream was encrypted with.
+    pub fn key_commitment(mut self, key_commitment: bool) -> Self {
+        self.key_commitment = key_commitment;
+        self
+    }
+}
+
+/// Options for decrypting a stream, built up by chaining setters onto [`DecryptOptions::new`].
+///
+/// # Examples
+///
+/// ```rust
+/// use gaia::{decrypt_with_options, encrypt_with_options, DecryptOptions, EncryptOptions, Identity};
+///
+/// let mut encrypted = Vec::new();
+/// let options = EncryptOptions::new().associated_data("tenant-42/report.pdf");
+/// let handle = encrypt_with_options("Hello, world!".as_bytes(), &mut encrypted, &options).unwrap();
+///
+/// let options = DecryptOptions::new().identity(Identity::Handle(handle));
+/// let mut decrypted = Vec::new();
+/// decrypt_with_options(encrypted.as_slice(), &options.clone().associated_data("tenant-42/report.pdf"), &mut decrypted).unwrap();
+/// assert_eq!(decrypted, b"Hello, world!");
+///
+/// let wrong_context = options.associated_data("tenant-43/report.pdf");
+/// assert!(decrypt_with_options(encrypted.as_slice(), &wrong_context, &mut Vec::new()).is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DecryptOptions {
+    pub(crate) identities: Vec<Identity>,
+    pub(crate) associated_data: Vec<u8>,
+}
+
+impl DecryptOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an identity to decrypt with. The identities are tried in the order they were added
+    /// until one of them can decrypt the stream.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identities.push(identity);
+        self
+    }
+
+    /// Adds each of the identities, as with [`DecryptOptions::identity`].
+    pub fn identities(mut self, identities: impl IntoIterator<Item = Identity>) -> Self {
+        self.identities.extend(identities);
+        self
+    }
+
+    /// Sets the associated data the stream was encrypted with, see [`EncryptOptions::associated_data`].
+    pub fn associated_data(mut self, associated_data: impl AsRef<[u8]>) -> Self {
+        self.associated_data = associated_data.as_ref().to_vec();
+        self
+    }
+
+    /// Sets up decryption of the chunks following a complete `preamble`.
+    pub(crate) fn open(&self, preamble: &[u8]) -> io::Result<Opened> {
+        let (header, handle) = open_preamble(&self.identities, preamble).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        let metadata = match header.flags & FLAG_METADATA {
+            0 => None,
+            _ => {
+                let offset = metadata_offset(preamble);
+                Some(open_metadata(&handle, &preamble[offset + 2..], &preamble[..offset])
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?)
+            }
+        };
+        let cipher = ChunkCipher::new(header.algorithm, &handle)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, HeaderError::HandleMismatch(header.algorithm)))?;
+        let decoder = match header.flags {
+            #[cfg(feature = "compression")]
+            flags if flags & FLAG_ZSTD != 0 => Some(PlaintextDecoder::decompress()?),
+            #[cfg(not(feature = "compression"))]
+            flags if flags & FLAG_ZSTD != 0 => return Err(io::Error::new(ErrorKind::Unsupported, HeaderError::UnsupportedFlags(flags))),
+            flags if flags & FLAG_PADDED != 0 => Some(PlaintextDecoder::unpad()),
+            _ => None,
+        };
+        Ok(Opened {
+            cipher,
+            chunk_length: header.chunk_size as usize + TAG_LENGTH,
+            associated_data: [preamble, &self.associated_data].concat(),
+            decoder,
+            metadata,
+        })
+    }
+}
+
+/// Everything needed to decrypt the chunks of a stream, as read from its preamble.
+pub(crate) struct Opened {
+    pub(crate) cipher: ChunkCipher,
+    pub(crate) chunk_length: usize, // Length of a single chunk of ciphertext, including the tag
+    pub(crate) associated_data: Vec<u8>, // The preamble followed by the associated data of the options
+    pub(crate) decoder: Option<PlaintextDecoder>, // Set if the plaintext is compressed or padded
+    pub(crate) metadata: Option<Metadata>,
+}
@@ -0,0 +1,247 @@
+//! Pads the plaintext so that the length of the ciphertext only gives away roughly how long the
+//! plaintext is, see [`Padding`].
+//!
+//! Padding is written after the plaintext once it has ended. Uncompressed plaintext is followed by
+//! a `0x80` byte and then zeros, so the padding starts at the last `0x80` byte that is only
+//! followed by zeros. Compressed plaintext is followed by zstd skippable frames instead, which
+//! decompression already ignores.
+
+use std::fmt::{Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+
+/// How the plaintext is padded before it is encrypted, see [`EncryptOptions::padding`](crate::EncryptOptions::padding).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Padding {
+    /// The plaintext is not padded.
+    #[default]
+    None,
+    /// The plaintext is padded with [PADMÉ](https://lbarman.ch/blog/padme/), which adds at most 12%
+    /// to its length and only lets the length of the ciphertext give away `O(log log n)` bits
+    /// of the length of the plaintext.
+    Padme,
+    /// The plaintext is padded to the next power of two, which adds up to 100% to its length
+    /// and gives away `O(log log n)` bits as well, but with fewer possible lengths.
+    PowerOfTwo,
+}
+
+impl Padding {
+    pub const ALL: [Padding; 3] = [Self::None, Self::Padme, Self::PowerOfTwo];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Padme => "padme",
+            Self::PowerOfTwo => "power-of-two",
+        }
+    }
+
+    /// Returns the length that `length` bytes are padded to.
+    pub fn padded_length(self, length: u64) -> u64 {
+        match self {
+            Padding::None => length,
+            Padding::PowerOfTwo => length.checked_next_power_of_two().unwrap_or(length),
+            Padding::Padme if length < 2 => length,
+            Padding::Padme => {
+                let exponent = length.ilog2();
+                let mantissa_bits = exponent.ilog2() + 1;
+                let mask = (1u64 << (exponent - mantissa_bits)) - 1;
+                length.checked_add(mask).map_or(length, |length| length & !mask)
+            }
+        }
+    }
+}
+
+impl Display for Padding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Padding {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|padding| padding.name().eq_ignore_ascii_case(name)).ok_or_else(|| {
+            let names: Vec<_> = Self::ALL.iter().map(|padding| padding.name()).collect();
+            format!("unknown padding '{name}', expected one of {}", names.join(", "))
+        })
+    }
+}
+
+/// The byte that uncompressed plaintext is followed by before the zeros of the padding.
+pub(crate) const MARKER: u8 = 0x80;
+
+/// The magic number of a zstd skippable frame, which is followed by the length of its body.
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A50;
+
+/// The length of the header of a zstd skippable frame.
+const SKIPPABLE_FRAME_HEADER_LENGTH: u64 = 8;
+
+/// The padding that follows the plaintext, written a piece at a time as it can be large.
+pub(crate) struct PaddingTail {
+    framed: bool, // Whether the padding is made of zstd skippable frames
+    length: u64, // Bytes of padding left to write
+    body: u64, // Zeros left in the current frame or after the marker
+}
+
+impl PaddingTail {
+    /// Sets up the padding after `length` bytes of plaintext, or of compressed plaintext if `framed`.
+    pub(crate) fn new(padding: Padding, length: u64, framed: bool) -> Self {
+        let length = match padding {
+            Padding::None => 0,
+            _ if framed => padding.padded_length(length + SKIPPABLE_FRAME_HEADER_LENGTH) - length,
+            _ => padding.padded_length(length + 1) - length,
+        };
+        Self { framed, length, body: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Appends about `limit` more bytes of the padding to `output`, a frame header more at most.
+    pub(crate) fn fill(&mut self, output: &mut Vec<u8>, limit: usize) {
+        let end = output.len() + limit;
+        while self.length > 0 && output.len() < end {
+            if self.body > 0 {
+                let length = self.body.min((end - output.len()) as u64);
+                output.resize(output.len() + length as usize, 0);
+                self.body -= length;
+                self.length -= length;
+            } else if self.framed {
+                // Whatever is left after the frame must fit the header of another one
+                let mut body = (self.length - SKIPPABLE_FRAME_HEADER_LENGTH).min(u32::MAX as u64);
+                if (1..SKIPPABLE_FRAME_HEADER_LENGTH).contains(&(self.length - SKIPPABLE_FRAME_HEADER_LENGTH - body)) {
+                    body -= SKIPPABLE_FRAME_HEADER_LENGTH;
+                }
+                output.extend_from_slice(&SKIPPABLE_FRAME_MAGIC.to_le_bytes());
+                output.extend_from_slice(&(body as u32).to_le_bytes());
+                self.length -= SKIPPABLE_FRAME_HEADER_LENGTH;
+                self.body = body;
+            } else {
+                output.push(MARKER);
+                self.length -= 1;
+                self.body = self.length;
+            }
+        }
+    }
+}
+
+/// Strips the padding off uncompressed plaintext a piece at a time.
+///
+/// A marker followed by zeros may be the padding, so it is held back until something other than a
+/// zero follows it. Only the number of zeros is kept, so long runs of zeros take no memory.
+#[derive(Default)]
+pub(crate) struct Unpadder {
+    marker: bool, // Whether a marker is held back
+    zeros: u64, // Zeros held back after the marker
+    releasing: bool, // Whether the held back bytes turned out not to be the padding
+}
+
+impl Unpadder {
+    /// Strips the padding off as much of `input` as fits into `output`, returning how many bytes
+    /// were read from the input and written to the output.
+    pub(crate) fn unpad(&mut self, input: &[u8], output: &mut [u8]) -> (usize, usize) {
+        let (mut read, mut written) = (0, 0);
+        loop {
+            if self.releasing {
+                if self.marker && written < output.len() {
+                    output[written] = MARKER;
+                    written += 1;
+                    self.marker = false;
+                }
+                let zeros = self.zeros.min((output.len() - written) as u64) as usize;
+                output[written..written + zeros].fill(0);
+                written += zeros;
+                self.zeros -= zeros as u64;
+                if self.marker || self.zeros > 0 {
+                    return (read, written);
+                }
+                self.releasing = false;
+            }
+
+            let rest = &input[read..];
+            if rest.is_empty() || written == output.len() {
+                return (read, written);
+            }
+
+            if self.marker {
+                let zeros = rest.iter().position(|&byte| byte != 0).unwrap_or(rest.len());
+                self.zeros += zeros as u64;
+                read += zeros;
+                self.releasing = zeros < rest.len();
+            } else {
+                let until_marker = rest.iter().position(|&byte| byte == MARKER).unwrap_or(rest.len());
+                let length = until_marker.min(output.len() - written);
+                output[written..written + length].copy_from_slice(&rest[..length]);
+                read += length;
+                written += length;
+                if length < rest.len() && rest[length] == MARKER {
+                    self.marker = true;
+                    read += 1;
+                }
+            }
+        }
+    }
+
+    /// Checks that the plaintext ended with its padding.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        match self.marker && !self.releasing {
+            true => Ok(()),
+            false => Err(io::Error::new(ErrorKind::InvalidData, "the padding of the plaintext is missing")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decrypt, encrypt_with_options, EncryptOptions, Handle};
+    use super::Padding;
+
+    fn encrypted(plaintext: &[u8], options: EncryptOptions) -> (Vec<u8>, Handle) {
+        let mut ciphertext = vec![];
+        let handle = encrypt_with_options(plaintext, &mut ciphertext, &options.chunk_size(64)).unwrap();
+        (ciphertext, handle)
+    }
+
+    fn assert_round_trips(plaintext: &[u8], options: EncryptOptions) {
+        let (ciphertext, handle) = encrypted(plaintext, options);
+        let mut decrypted = vec![];
+        decrypt(ciphertext.as_slice(), &handle, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext, "{} bytes", plaintext.len());
+    }
+
+    #[test]
+    fn padded_length() {
+        assert_eq!([0, 1, 1000, 1024, 1025].map(|length| Padding::Padme.padded_length(length)), [0, 1, 1024, 1024, 1088]);
+        assert_eq!([0, 1, 1000, 1024, 1025].map(|length| Padding::PowerOfTwo.padded_length(length)), [1, 1, 1024, 1024, 2048]);
+        assert_eq!(Padding::PowerOfTwo.padded_length(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn padded_round_trip() {
+        for padding in Padding::ALL {
+            // Plaintext that ends in what looks like padding must not lose it
+            for plaintext in [vec![], vec![0x80], vec![0x80, 0], vec![7; 63], vec![7; 64], vec![0x80; 65], vec![0; 1000]] {
+                assert_round_trips(&plaintext, EncryptOptions::new().padding(padding));
+            }
+        }
+    }
+
+    #[test]
+    fn padding_hides_the_exact_length() {
+        let lengths: Vec<_> = (1000..1010).map(|length| encrypted(&vec![7; length], EncryptOptions::new().padding(Padding::Padme)).0.len()).collect();
+        assert!(lengths.iter().all(|&length| length == lengths[0]));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn padded_and_compressed_round_trip() {
+        for padding in Padding::ALL {
+            for plaintext in [vec![], b"All work and no play makes Jack a dull boy. ".repeat(100)] {
+                assert_round_trips(&plaintext, EncryptOptions::new().padding(padding).compression(crate::Compression::Zstd(3)));
+            }
+        }
+    }
+}
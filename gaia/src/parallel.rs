@@ -0,0 +1,230 @@
+//! Encrypts and decrypts many chunks at once on the [rayon] thread pool.
+//!
+//! The input is read in batches of chunks. While one batch is transformed on the pool, the
+//! previous one is written out and the next one is read, so no more than three batches are held
+//! in memory at a time. The output is the same as that of the sequential functions.
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use aead::OsRng;
+use rayon::prelude::*;
+
+use crate::{error::GaiaError, DecryptOptions, EncryptOptions, Handle};
+use crate::algorithm::{ChunkCipher, TAG_LENGTH};
+use crate::encoding::{DecodingWriter, EncodingInput, EncodingReader};
+use crate::header::{missing_preamble_bytes, HeaderError};
+use crate::options::Opened;
+use crate::recipient::write_preamble;
+
+/// The number of chunks in a batch for every thread in the pool.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// Like [`encrypt_with_options`](crate::encrypt_with_options), but encrypts the chunks in parallel.
+pub fn encrypt_parallel(input: impl Read, mut output: impl Write, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let handle = Handle::generate(options.algorithm, &mut OsRng);
+    let preamble = write_preamble(&handle, options).map_err(GaiaError::WritingOutput)?;
+    output.write_all(&preamble).map_err(GaiaError::WritingOutput)?;
+
+    let cipher = ChunkCipher::new(options.algorithm, &handle).expect("the handle was generated for the algorithm");
+    let associated_data = [preamble.as_slice(), &options.associated_data].concat();
+    let encrypt_chunk = |position, last_chunk, chunk: &mut Vec<u8>| {
+        cipher.encrypt_in_place(position, last_chunk, &associated_data, chunk).map_err(GaiaError::Encrypting)
+    };
+    match EncodingInput::new(options) {
+        Some(encoding) => transform_parallel(EncodingReader::new(input, encoding), output, options.chunk_size, encrypt_chunk)?,
+        None => transform_parallel(input, output, options.chunk_size, encrypt_chunk)?,
+    }
+    Ok(handle)
+}
+
+/// Like [`decrypt_with_options`](crate::decrypt_with_options), but decrypts the chunks in parallel.
+pub fn decrypt_parallel(mut input: impl Read, options: &DecryptOptions, output: impl Write) -> Result<(), GaiaError> {
+    let preamble = read_preamble(&mut input)?;
+    let Opened { cipher, chunk_length, associated_data, decoder, .. } = options.open(&preamble).map_err(GaiaError::from_output_error)?;
+    let decrypt_chunk = |position, last_chunk, chunk: &mut Vec<u8>| {
+        cipher.decrypt_in_place(position, last_chunk, &associated_data, chunk).map_err(GaiaError::Decrypting)
+    };
+    match decoder {
+        Some(decoder) => {
+            let mut output = DecodingWriter::new(output, decoder);
+            transform_parallel(input, &mut output, chunk_length, decrypt_chunk)?;
+            output.finish().map_err(GaiaError::WritingOutput)
+        }
+        None => transform_parallel(input, output, chunk_length, decrypt_chunk),
+    }
+}
+
+fn read_preamble(input: &mut impl Read) -> Result<Vec<u8>, GaiaError> {
+    let mut preamble = vec![];
+    loop {
+        let missing = missing_preamble_bytes(&preamble).map_err(GaiaError::InvalidHeader)?;
+        if missing == 0 {
+            return Ok(preamble);
+        }
+
+        let read = input.by_ref().take(missing as u64).read_to_end(&mut preamble).map_err(GaiaError::ReadingInput)?;
+        if read < missing {
+            // Short foreign input is reported as such rather than as truncated
+            missing_preamble_bytes(&preamble).map_err(GaiaError::InvalidHeader)?;
+            return Err(GaiaError::InvalidHeader(HeaderError::Truncated));
+        }
+    }
+}
+
+/// Transforms `input` in chunks of `chunk_length` bytes with `transform`, which is given the
+/// position of each chunk and whether it is the last one.
+fn transform_parallel<F>(input: impl Read, mut output: impl Write, chunk_length: usize, transform: F) -> Result<(), GaiaError>
+where F: Fn(u32, bool, &mut Vec<u8>) -> Result<(), GaiaError> + Sync {
+    let batch_size = rayon::current_num_threads() * CHUNKS_PER_THREAD;
+    let mut chunks = ChunkReader::new(input, chunk_length).map_err(GaiaError::ReadingInput)?;
+
+    let mut position = 0u32;
+    let mut transformed: Vec<Vec<u8>> = vec![];
+    let mut batch = vec![];
+    chunks.read_batch(&mut batch, batch_size).map_err(GaiaError::ReadingInput)?;
+    while !batch.is_empty() {
+        let first_position = position;
+        let last_position = first_position + (batch.len() - 1) as u32;
+        let last_batch = chunks.finished;
+
+        let mut transform_result = Ok(());
+        let mut io_result = Ok(());
+        rayon::in_place_scope(|scope| {
+            scope.spawn(|_| {
+                // Every chunk is transformed, so that the error of the first chunk that fails is
+                // returned as it would be by the sequential functions, not whichever comes first
+                let results: Vec<_> = batch.par_iter_mut().enumerate().map(|(index, chunk)| {
+                    let position = first_position + index as u32;
+                    transform(position, last_batch && position == last_position, chunk)
+                }).collect();
+                transform_result = results.into_iter().collect();
+            });
+
+            // Meanwhile, write out the previous batch and read the next one into its buffers
+            io_result = write_batch(&mut output, &transformed).map_err(GaiaError::WritingOutput)
+                .and_then(|_| chunks.read_batch(&mut transformed, batch_size).map_err(GaiaError::ReadingInput));
+        });
+        transform_result?;
+        io_result?;
+
+        position = last_position.wrapping_add(1);
+        mem::swap(&mut batch, &mut transformed);
+    }
+
+    write_batch(&mut output, &transformed).map_err(GaiaError::WritingOutput)?;
+    output.flush().map_err(GaiaError::WritingOutput)
+}
+
+fn write_batch(output: &mut impl Write, batch: &[Vec<u8>]) -> io::Result<()> {
+    batch.iter().try_for_each(|chunk| output.write_all(chunk))
+}
+
+/// Reads chunks one ahead, so that the last chunk is known when it is read.
+struct ChunkReader<R> where R: Read {
+    input: R,
+    chunk_length: usize,
+    next: Vec<u8>,
+    finished: bool,
+}
+
+impl<R> ChunkReader<R> where R: Read {
+    fn new(input: R, chunk_length: usize) -> io::Result<Self> {
+        let mut reader = Self { input, chunk_length, next: Vec::with_capacity(chunk_length + TAG_LENGTH), finished: false };
+        reader.read_next()?;
+        Ok(reader)
+    }
+
+    fn read_next(&mut self) -> io::Result<()> {
+        self.next.clear();
+        self.input.by_ref().take(self.chunk_length as u64).read_to_end(&mut self.next)?;
+        Ok(())
+    }
+
+    /// Reads up to `size` chunks into `batch`, reusing the buffers of the chunks that were in it.
+    /// The last chunk of the input, which may be empty, ends up last in its batch, after which
+    /// [`finished`](Self::finished) is set and only empty batches are read.
+    fn read_batch(&mut self, batch: &mut Vec<Vec<u8>>, size: usize) -> io::Result<()> {
+        let mut length = 0;
+        while !self.finished && length < size {
+            if length == batch.len() {
+                batch.push(Vec::with_capacity(self.chunk_length + TAG_LENGTH));
+            }
+
+            // The chunk read ahead goes into the batch, and the buffer it replaces is read into next
+            mem::swap(&mut self.next, &mut batch[length]);
+            self.read_next()?;
+            self.finished = self.next.is_empty();
+            length += 1;
+        }
+        batch.truncate(length);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{decrypt, decrypt_parallel, encrypt_parallel, generate_handle, DecryptOptions, EncryptOptions, EncryptingReader, Identity};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+
+    const CHUNK_SIZE: usize = 64;
+
+    #[test]
+    fn output_is_identical_to_streaming() {
+        // Enough chunks for several batches, ending in a full chunk or a partial one
+        let batch_size = rayon::current_num_threads() * super::CHUNKS_PER_THREAD;
+        for length in [0, 1, CHUNK_SIZE, 3 * batch_size * CHUNK_SIZE, 3 * batch_size * CHUNK_SIZE + 5] {
+            let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let options = EncryptOptions::new().chunk_size(CHUNK_SIZE).key_commitment(true);
+            let mut ciphertext = vec![];
+            let handle = encrypt_parallel(plaintext.as_slice(), &mut ciphertext, &options).unwrap();
+
+            let mut streamed = vec![];
+            EncryptingReader::with_options(plaintext.as_slice(), &handle, &options).read_to_end(&mut streamed).unwrap();
+            assert_eq!(ciphertext, streamed, "{length} bytes");
+
+            let mut decrypted = vec![];
+            let options = DecryptOptions::new().identity(Identity::Handle(handle.clone()));
+            decrypt_parallel(ciphertext.as_slice(), &options, &mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext, "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn errors_are_identical_to_streaming() {
+        let plaintext = vec![7; 10 * CHUNK_SIZE + 5];
+        let mut ciphertext = vec![];
+        let handle = encrypt_parallel(plaintext.as_slice(), &mut ciphertext, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).unwrap();
+        let options = DecryptOptions::new().identity(Identity::Handle(handle.clone()));
+
+        let mut tampered = ciphertext.clone();
+        tampered[ciphertext.len() - 3 * CHUNK_SIZE] ^= 1;
+        let mut tampered_twice = ciphertext.clone();
+        tampered_twice[ciphertext.len() - 3 * CHUNK_SIZE] ^= 1;
+        tampered_twice[ciphertext.len() - 8 * CHUNK_SIZE] ^= 1;
+        let truncated = &ciphertext[..ciphertext.len() - 5 - TAG_LENGTH];
+        let extended = [ciphertext.as_slice(), &[0; 100]].concat();
+        for input in [tampered.as_slice(), &tampered_twice, truncated, &extended] {
+            let parallel = decrypt_parallel(input, &options, &mut vec![]).unwrap_err();
+            let streamed = decrypt(input, &handle, &mut vec![]).unwrap_err();
+            assert_eq!(format!("{parallel}"), format!("{streamed}"));
+        }
+    }
+
+    #[test]
+    fn wrong_key_fails_at_the_first_chunk() {
+        // Every chunk fails with the wrong key, so only the first is the one to report
+        let plaintext = vec![7; 100 * CHUNK_SIZE];
+        let mut ciphertext = vec![];
+        encrypt_parallel(plaintext.as_slice(), &mut ciphertext, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).unwrap();
+        let options = DecryptOptions::new().identity(Identity::Handle(generate_handle(&mut aead::OsRng)));
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+        for _ in 0..20 {
+            let error = pool.install(|| decrypt_parallel(ciphertext.as_slice(), &options, &mut vec![])).unwrap_err();
+            assert!(matches!(error, GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 0, .. })), "{error:?}");
+        }
+    }
+}
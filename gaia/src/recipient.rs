@@ -0,0 +1,458 @@
+use std::fmt::{Debug, Formatter};
+use std::io::{self, ErrorKind};
+
+use aead::{Aead, KeyInit, OsRng, Payload};
+use aead::generic_array::GenericArray;
+use aead::rand_core::RngCore;
+use argon2::{Argon2, Params, Version};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+
+use crate::{EncryptOptions, Handle, KEY_LENGTH};
+use crate::header::{stanzas_end, COMMITMENT_LENGTH, FLAG_KEY_COMMITMENT, FLAG_METADATA, FLAG_PADDED, FLAG_RECIPIENTS, FLAG_ZSTD, HEADER_LENGTH, Header, HeaderError, Stanza};
+use crate::metadata::seal_metadata;
+use crate::padding::Padding;
+
+/// The kind of a stanza wrapping the key for a passphrase.
+const PASSPHRASE_STANZA: u8 = 1;
+
+/// The kind of a stanza wrapping the key for an X25519 public key.
+const X25519_STANZA: u8 = 2;
+
+/// The kind of a stanza wrapping the key for another handle.
+const HANDLE_STANZA: u8 = 3;
+
+/// Domain separation for the key that wraps a handle stanza.
+const HANDLE_INFO: &[u8] = b"gaia handle stanza";
+
+/// Domain separation for the key that wraps an X25519 stanza.
+const X25519_INFO: &[u8] = b"gaia x25519 stanza";
+
+/// Domain separation for the commitment to the key of a stream.
+const COMMITMENT_INFO: &[u8] = b"gaia key commitment";
+
+const SALT_LENGTH: usize = 16;
+
+/// The most recipients a stream can have, as their number is stored in a single byte.
+pub const MAX_RECIPIENTS: usize = u8::MAX as usize;
+
+/// Argon2id cost parameters for deriving a key from a passphrase.
+///
+/// The defaults follow the OWASP recommendation of 19 MiB of memory, 2 iterations and
+/// no parallelism. Raising them makes guessing the passphrase slower, but decryption too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassphraseParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PassphraseParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl PassphraseParams {
+    /// The most memory that can be used, in KiB. Streams asking for more are refused rather than
+    /// allowed to exhaust the memory of whoever decrypts them.
+    pub const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+    /// The most passes over the memory that can be made, for the same reason.
+    pub const MAX_ITERATIONS: u32 = 64;
+    /// The most lanes that can be used, for the same reason.
+    pub const MAX_PARALLELISM: u32 = 16;
+
+    /// Whether the costs are within [`MAX_MEMORY_KIB`](Self::MAX_MEMORY_KIB), [`MAX_ITERATIONS`](Self::MAX_ITERATIONS)
+    /// and [`MAX_PARALLELISM`](Self::MAX_PARALLELISM), which is checked before a key is derived.
+    pub fn within_limits(&self) -> bool {
+        self.memory_kib <= Self::MAX_MEMORY_KIB && self.iterations <= Self::MAX_ITERATIONS && self.parallelism <= Self::MAX_PARALLELISM
+    }
+
+    fn encode(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&self.memory_kib.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.iterations.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.parallelism.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let word = |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        Self { memory_kib: word(0), iterations: word(1), parallelism: word(2) }
+    }
+
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LENGTH], argon2::Error> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_LENGTH))?;
+        let mut key = [0u8; KEY_LENGTH];
+        Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)?;
+        Ok(key)
+    }
+}
+
+/// Someone a stream is encrypted for, who can then decrypt it with the matching [`Identity`]
+/// instead of the [`Handle`] of the stream.
+///
+/// # Examples
+///
+/// ```rust
+/// use gaia::{decrypt_with_identity, encrypt_for, Identity, Recipient};
+/// use gaia::x25519_dalek::{PublicKey, StaticSecret};
+///
+/// let (alice, bob) = (StaticSecret::random_from_rng(rand_core::OsRng), StaticSecret::random_from_rng(rand_core::OsRng));
+/// let recipients = [Recipient::X25519(PublicKey::from(&alice)), Recipient::X25519(PublicKey::from(&bob))];
+/// let mut encrypted = Vec::new();
+/// encrypt_for("Hello, world!".as_bytes(), &mut encrypted, &recipients).unwrap();
+///
+/// for identity in [Identity::X25519(alice), Identity::X25519(bob)] {
+///     let mut decrypted = Vec::new();
+///     decrypt_with_identity(encrypted.as_slice(), &identity, &mut decrypted).unwrap();
+///     assert_eq!(decrypted, b"Hello, world!");
+/// }
+/// ```
+#[derive(Clone)]
+pub enum Recipient {
+    /// Wraps the key with a key derived from the passphrase with Argon2id and a random salt.
+    /// A passphrase must be the only recipient of a stream.
+    Passphrase(String, PassphraseParams),
+    /// Wraps the key with a key agreed on between the public key and a fresh ephemeral key.
+    X25519(PublicKey),
+    /// Wraps the key with a key derived from another handle and a random salt, so that a
+    /// long-lived handle can decrypt any number of streams.
+    Handle(Handle),
+}
+
+impl Recipient {
+    /// A passphrase recipient with the default cost parameters.
+    pub fn passphrase(passphrase: impl Into<String>) -> Self {
+        Self::Passphrase(passphrase.into(), PassphraseParams::default())
+    }
+
+    /// Wraps `handle` into a stanza for this recipient, binding it to `header`.
+    pub(crate) fn wrap(&self, handle: &Handle, header: &[u8; HEADER_LENGTH]) -> io::Result<Stanza> {
+        match self {
+            Self::Passphrase(passphrase, params) => {
+                let mut salt = [0u8; SALT_LENGTH];
+                OsRng.fill_bytes(&mut salt);
+
+                if !params.within_limits() {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "the Argon2 costs are above the limits that decryption accepts"));
+                }
+                let key = params.derive_key(passphrase, &salt).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+                let mut body = [salt.as_slice(), &params.encode()].concat();
+                body.extend(seal_handle(&key, handle, header));
+                Ok(Stanza { kind: PASSPHRASE_STANZA, body })
+            }
+            Self::Handle(recipient) => {
+                let mut salt = [0u8; SALT_LENGTH];
+                OsRng.fill_bytes(&mut salt);
+
+                let mut body = salt.to_vec();
+                body.extend(seal_handle(&derive_handle_key(recipient, &salt), handle, header));
+                Ok(Stanza { kind: HANDLE_STANZA, body })
+            }
+            Self::X25519(public_key) => {
+                let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+                let ephemeral_public = PublicKey::from(&ephemeral);
+                let key = derive_x25519_key(ephemeral.diffie_hellman(public_key), &ephemeral_public, public_key)
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "the public key of the recipient is invalid"))?;
+
+                let mut body = ephemeral_public.as_bytes().to_vec();
+                body.extend(seal_handle(&key, handle, header));
+                Ok(Stanza { kind: X25519_STANZA, body })
+            }
+        }
+    }
+}
+
+impl Debug for Recipient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passphrase(_, params) => f.debug_tuple("Passphrase").field(&"..").field(params).finish(),
+            Self::X25519(public_key) => f.debug_tuple("X25519").field(public_key).finish(),
+            Self::Handle(handle) => f.debug_tuple("Handle").field(handle).finish(),
+        }
+    }
+}
+
+/// Something that can decrypt a stream.
+#[derive(Clone)]
+pub enum Identity {
+    /// The handle the stream was encrypted with, or one it was encrypted for with [`Recipient::Handle`].
+    Handle(Handle),
+    /// A passphrase the stream was encrypted for with [`Recipient::Passphrase`].
+    Passphrase(String),
+    /// The secret key to a public key the stream was encrypted for with [`Recipient::X25519`].
+    X25519(StaticSecret),
+}
+
+impl Identity {
+    /// Unwraps the handle of a stream from one of its stanzas, or returns `None` if the stanza is
+    /// not meant for this identity.
+    fn unwrap(&self, stanza: &Stanza, header: &[u8]) -> Result<Option<Handle>, HeaderError> {
+        Ok(match (self, stanza.kind) {
+            (Self::Handle(handle), HANDLE_STANZA) => {
+                let (salt, sealed) = stanza.body.split_at_checked(SALT_LENGTH).ok_or(HeaderError::MalformedRecipients)?;
+                open_handle(&derive_handle_key(handle, salt), sealed, header)
+            }
+            (Self::Passphrase(passphrase), PASSPHRASE_STANZA) => {
+                if stanza.body.len() < SALT_LENGTH + 12 {
+                    return Err(HeaderError::MalformedRecipients);
+                }
+
+                let (salt, rest) = stanza.body.split_at(SALT_LENGTH);
+                let (params, sealed) = rest.split_at(12);
+                let params = PassphraseParams::decode(params);
+                if !params.within_limits() {
+                    return Err(HeaderError::ExcessivePassphraseCost(params));
+                }
+                let key = params.derive_key(passphrase, salt).map_err(|_| HeaderError::MalformedRecipients)?;
+                open_handle(&key, sealed, header)
+            }
+            (Self::X25519(secret), X25519_STANZA) => {
+                let (ephemeral_public, sealed) = stanza.body.split_at_checked(32).ok_or(HeaderError::MalformedRecipients)?;
+                let ephemeral_public = PublicKey::from(<[u8; 32]>::try_from(ephemeral_public).unwrap());
+                let key = derive_x25519_key(secret.diffie_hellman(&ephemeral_public), &ephemeral_public, &PublicKey::from(secret))
+                    .ok_or(HeaderError::MalformedRecipients)?;
+                open_handle(&key, sealed, header)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Handle(handle) => f.debug_tuple("Handle").field(handle).finish(),
+            Self::Passphrase(_) => f.debug_tuple("Passphrase").field(&"..").finish(),
+            Self::X25519(secret) => f.debug_tuple("X25519").field(&PublicKey::from(secret)).finish(),
+        }
+    }
+}
+
+/// Encodes the header of a stream encrypted with `handle` and `options`, followed by the stanzas
+/// of its recipients, the commitment to its key and its sealed metadata if it has any.
+pub(crate) fn write_preamble(handle: &Handle, options: &EncryptOptions) -> io::Result<Vec<u8>> {
+    let mut header = Header { algorithm: options.algorithm, chunk_size: options.chunk_size as u32, ..Header::default() };
+    if options.compresses() {
+        header.flags |= FLAG_ZSTD;
+    }
+    if options.padding != Padding::None {
+        header.flags |= FLAG_PADDED;
+    }
+    if options.metadata.is_some() {
+        header.flags |= FLAG_METADATA;
+    }
+    if options.key_commitment {
+        header.flags |= FLAG_KEY_COMMITMENT;
+    }
+    if options.recipients.len() > MAX_RECIPIENTS {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("a stream can have at most {MAX_RECIPIENTS} recipients")));
+    }
+    if options.recipients.len() > 1 && options.recipients.iter().any(|recipient| matches!(recipient, Recipient::Passphrase(..))) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "a passphrase must be the only recipient of a stream"));
+    }
+    if !options.recipients.is_empty() {
+        header.flags |= FLAG_RECIPIENTS;
+    }
+
+    let header = header.to_bytes();
+    let mut preamble = header.to_vec();
+    if !options.recipients.is_empty() {
+        let stanzas = options.recipients.iter().map(|recipient| recipient.wrap(handle, &header)).collect::<io::Result<Vec<_>>>()?;
+        preamble.extend_from_slice(&Stanza::encode_all(&stanzas));
+    }
+    if options.key_commitment {
+        preamble.extend_from_slice(&commit_to(handle));
+    }
+    if let Some(ref metadata) = options.metadata {
+        let sealed = seal_metadata(handle, metadata, &preamble)?;
+        preamble.extend_from_slice(&(sealed.len() as u16).to_le_bytes());
+        preamble.extend_from_slice(&sealed);
+    }
+    Ok(preamble)
+}
+
+/// Finds the header and handle of a stream from its complete `preamble`, as checked with
+/// [`missing_preamble_bytes`](crate::header::missing_preamble_bytes), by trying each identity in
+/// turn on each stanza.
+///
+/// A stanza that does not unwrap, because it is malformed or meant for someone else, is skipped,
+/// and only if no identity opens any stanza is the first such error returned.
+///
+/// A passphrase stanza must be the only stanza of a stream, so that a crafted stream cannot make
+/// each passphrase identity run Argon2 once for every one of [`MAX_RECIPIENTS`] stanzas.
+///
+/// If the stream commits to its key, only a handle that matches the commitment is returned.
+pub(crate) fn open_preamble(identities: &[Identity], preamble: &[u8]) -> Result<(Header, Handle), HeaderError> {
+    let header = Header::from_bytes(preamble)?;
+    let commitment = (header.flags & FLAG_KEY_COMMITMENT != 0)
+        .then(|| &preamble[stanzas_end(preamble)..stanzas_end(preamble) + COMMITMENT_LENGTH]);
+    let matches_commitment = |handle: &Handle| commitment.is_none_or(|commitment| commit_to(handle).ct_eq(commitment));
+    let mut mismatched = false;
+    let mut skipped = None;
+
+    if header.flags & FLAG_RECIPIENTS != 0 {
+        let stanzas = Stanza::decode_all(&preamble[HEADER_LENGTH..]);
+        if stanzas.len() > 1 && stanzas.iter().any(|stanza| stanza.kind == PASSPHRASE_STANZA) {
+            return Err(HeaderError::MalformedRecipients);
+        }
+        for identity in identities {
+            for stanza in &stanzas {
+                let unwrapped = identity.unwrap(stanza, &preamble[..HEADER_LENGTH]).and_then(|handle| match handle {
+                    Some(handle) if handle.nonce().len() != header.algorithm.nonce_length() => Err(HeaderError::MalformedRecipients),
+                    handle => Ok(handle),
+                });
+                let handle = match unwrapped {
+                    Ok(Some(handle)) => handle,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        skipped.get_or_insert(e);
+                        continue;
+                    }
+                };
+                if matches_commitment(&handle) {
+                    return Ok((header, handle));
+                }
+                mismatched = true;
+            }
+        }
+    }
+
+    // Otherwise, one of the handles may be the one the stream was encrypted with
+    let handles = identities.iter().filter_map(|identity| match identity {
+        Identity::Handle(handle) => Some(handle),
+        _ => None,
+    });
+    for handle in handles {
+        if matches_commitment(handle) {
+            return Ok((header, handle.clone()));
+        }
+        mismatched = true;
+    }
+
+    Err(match mismatched {
+        true => HeaderError::CommitmentMismatch,
+        false => skipped.unwrap_or(HeaderError::NoMatchingIdentity),
+    })
+}
+
+/// Derives the commitment to the key and nonce of `handle`, which no other handle can be found for.
+fn commit_to(handle: &Handle) -> Commitment {
+    let mut commitment = [0u8; COMMITMENT_LENGTH];
+    Hkdf::<Sha256>::new(None, &[handle.key().as_slice(), handle.nonce()].concat())
+        .expand(COMMITMENT_INFO, &mut commitment).unwrap();
+    Commitment(commitment)
+}
+
+/// A commitment to the key of a stream, which is compared in constant time.
+struct Commitment([u8; COMMITMENT_LENGTH]);
+
+impl Commitment {
+    fn ct_eq(&self, other: &[u8]) -> bool {
+        other.len() == COMMITMENT_LENGTH && self.0.iter().zip(other).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+    }
+}
+
+impl std::ops::Deref for Commitment {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Derives the key wrapping a handle stanza from the handle of the recipient and the salt of the stanza.
+fn derive_handle_key(recipient: &Handle, salt: &[u8]) -> [u8; KEY_LENGTH] {
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(salt), &[recipient.key().as_slice(), recipient.nonce()].concat())
+        .expand(HANDLE_INFO, &mut key).unwrap();
+    key
+}
+
+/// Derives the key wrapping an X25519 stanza from the agreed secret and both public keys, or returns
+/// `None` if a low-order public key made the secret predictable.
+fn derive_x25519_key(shared: SharedSecret, ephemeral: &PublicKey, recipient: &PublicKey) -> Option<[u8; KEY_LENGTH]> {
+    if !shared.was_contributory() {
+        return None;
+    }
+
+    let salt = [ephemeral.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes()).expand(X25519_INFO, &mut key).unwrap();
+    Some(key)
+}
+
+/// Encrypts the key and nonce of `handle` with `key`, which must never be used to seal anything else.
+fn seal_handle(key: &[u8; KEY_LENGTH], handle: &Handle, header: &[u8]) -> Vec<u8> {
+    let plaintext = [handle.key().as_slice(), handle.nonce()].concat();
+    ChaCha20Poly1305::new(GenericArray::from_slice(key))
+        .encrypt(&Default::default(), Payload { msg: &plaintext, aad: header })
+        .expect("sealing a handle cannot fail")
+}
+
+fn open_handle(key: &[u8; KEY_LENGTH], sealed: &[u8], header: &[u8]) -> Option<Handle> {
+    let plaintext = ChaCha20Poly1305::new(GenericArray::from_slice(key))
+        .decrypt(&Default::default(), Payload { msg: sealed, aad: header }).ok()?;
+    let (key, nonce) = plaintext.split_at_checked(KEY_LENGTH)?;
+    Handle::new(key.try_into().unwrap(), nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use crate::{decrypt_with_identity, encrypt_for, Identity, Recipient};
+    use crate::header::{Header, HeaderError, Stanza, FLAG_RECIPIENTS};
+    use super::{open_preamble, PassphraseParams, MAX_RECIPIENTS, PASSPHRASE_STANZA, SALT_LENGTH, X25519_STANZA};
+
+    const CHEAP: PassphraseParams = PassphraseParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+
+    /// A preamble with the given stanzas, whose bodies are the right length but seal nothing.
+    fn preamble(kinds: &[u8]) -> Vec<u8> {
+        let expensive = PassphraseParams { memory_kib: PassphraseParams::MAX_MEMORY_KIB, iterations: PassphraseParams::MAX_ITERATIONS, parallelism: 1 };
+        let stanzas: Vec<_> = kinds.iter().map(|&kind| match kind {
+            PASSPHRASE_STANZA => Stanza { kind, body: [[0; SALT_LENGTH].as_slice(), &expensive.encode(), &[0; 60]].concat() },
+            _ => Stanza { kind, body: vec![0; 32 + 60] },
+        }).collect();
+        let header = Header { flags: FLAG_RECIPIENTS, ..Header::default() };
+        [header.to_bytes().as_slice(), &Stanza::encode_all(&stanzas)].concat()
+    }
+
+    #[test]
+    fn passphrase_round_trip() {
+        let mut encrypted = vec![];
+        encrypt_for(b"Hello, world!".as_slice(), &mut encrypted, &[Recipient::Passphrase("correct horse".to_string(), CHEAP)]).unwrap();
+
+        let mut decrypted = vec![];
+        decrypt_with_identity(encrypted.as_slice(), &Identity::Passphrase("correct horse".to_string()), &mut decrypted).unwrap();
+        assert_eq!(decrypted, b"Hello, world!");
+        assert!(decrypt_with_identity(encrypted.as_slice(), &Identity::Passphrase("battery staple".to_string()), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn many_passphrase_stanzas_are_refused_before_deriving_any_key() {
+        // Deriving a key for even one of these stanzas would take far longer than the test
+        let identities = [Identity::Passphrase("correct horse".to_string())];
+        let stanzas = [PASSPHRASE_STANZA; MAX_RECIPIENTS];
+        assert!(matches!(open_preamble(&identities, &preamble(&stanzas)), Err(HeaderError::MalformedRecipients)));
+
+        for kinds in [[PASSPHRASE_STANZA, X25519_STANZA], [X25519_STANZA, PASSPHRASE_STANZA]] {
+            assert!(matches!(open_preamble(&identities, &preamble(&kinds)), Err(HeaderError::MalformedRecipients)));
+        }
+    }
+
+    #[test]
+    fn passphrase_cannot_be_mixed_with_other_recipients() {
+        let secret = StaticSecret::random_from_rng(rand_core::OsRng);
+        let recipients = [Recipient::Passphrase("correct horse".to_string(), CHEAP), Recipient::X25519(PublicKey::from(&secret))];
+        assert!(encrypt_for(b"Hello, world!".as_slice(), &mut vec![], &recipients).is_err());
+    }
+}
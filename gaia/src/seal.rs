@@ -0,0 +1,158 @@
+//! Seals small messages, like tokens and configuration blobs, with a single AEAD call instead of a stream.
+
+use std::fmt::{Debug, Formatter};
+
+use aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+use aead::generic_array::GenericArray;
+use aead::rand_core::{CryptoRng, RngCore};
+use chacha20poly1305::XChaCha20Poly1305;
+
+use crate::{error::GaiaError, KEY_LENGTH};
+use crate::algorithm::TAG_LENGTH;
+use crate::header::HeaderError;
+
+/// The version of the sealed format, which is the first byte of every sealed message.
+const SEAL_VERSION: u8 = 1;
+
+/// The length of the random nonce that follows the version.
+const NONCE_LENGTH: usize = 24;
+
+/// How many bytes longer a sealed message is than its plaintext.
+pub const SEAL_OVERHEAD: usize = 1 + NONCE_LENGTH + TAG_LENGTH;
+
+/// A key for [`seal`] and [`open`].
+///
+/// Unlike a [`Handle`](crate::Handle), a key has no nonce. Every sealed message gets its own
+/// random 192-bit XChaCha20-Poly1305 nonce instead, which is long enough that a key can seal any
+/// number of messages without a nonce being reused.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LENGTH]);
+
+impl Key {
+    pub fn new(bytes: [u8; KEY_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    /// Generates a key using the provided cryptographically secure random number generator.
+    pub fn generate(rng: &mut (impl CryptoRng + RngCore)) -> Self {
+        let mut bytes = [0u8; KEY_LENGTH];
+        rng.fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LENGTH] {
+        &self.0
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(GenericArray::from_slice(&self.0))
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key").finish_non_exhaustive()
+    }
+}
+
+/// Seals `plaintext` with `key`, binding it to `associated_data`, which must be given again to open it.
+///
+/// The sealed message is the version of the format, a random nonce, the ciphertext and a tag,
+/// which is [`SEAL_OVERHEAD`] bytes longer than the plaintext.
+///
+/// ```rust
+/// use gaia::{seal, open, Key};
+/// use rand_core::OsRng;
+///
+/// let key = Key::generate(&mut OsRng);
+/// let sealed = seal(&key, b"hunter2", b"database password");
+/// assert_eq!(open(&key, &sealed, b"database password").unwrap(), b"hunter2");
+/// assert!(open(&key, &sealed, b"api token").is_err());
+/// ```
+pub fn seal(key: &Key, plaintext: &[u8], associated_data: &[u8]) -> Vec<u8> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut sealed = Vec::with_capacity(SEAL_OVERHEAD + plaintext.len());
+    sealed.push(SEAL_VERSION);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(plaintext);
+
+    let tag = key.cipher().encrypt_in_place_detached(&nonce, &[&[SEAL_VERSION], associated_data].concat(), &mut sealed[1 + NONCE_LENGTH..])
+        .expect("small messages fit XChaCha20-Poly1305");
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Opens a message sealed with [`seal`], failing if it was sealed with another key or other
+/// associated data, or has been changed since.
+pub fn open(key: &Key, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, GaiaError> {
+    match sealed.first() {
+        None => return Err(GaiaError::InvalidHeader(HeaderError::Truncated)),
+        Some(&version) if version != SEAL_VERSION => return Err(GaiaError::InvalidHeader(HeaderError::UnsupportedVersion(version))),
+        Some(_) if sealed.len() < SEAL_OVERHEAD => return Err(GaiaError::InvalidHeader(HeaderError::Truncated)),
+        Some(_) => {}
+    }
+
+    let (nonce, ciphertext) = sealed[1..].split_at(NONCE_LENGTH);
+    let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LENGTH);
+    let mut plaintext = ciphertext.to_vec();
+    key.cipher().decrypt_in_place_detached(GenericArray::from_slice(nonce), &[&[SEAL_VERSION], associated_data].concat(),
+        &mut plaintext, GenericArray::from_slice(tag))
+        .map_err(|_| GaiaError::Unsealing)?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use aead::OsRng;
+
+    use crate::error::GaiaError;
+    use crate::header::HeaderError;
+    use super::{open, seal, Key, SEAL_OVERHEAD};
+
+    #[test]
+    fn round_trip() {
+        let key = Key::generate(&mut OsRng);
+        for plaintext in [b"".as_slice(), b"hunter2", &[7; 1000]] {
+            let sealed = seal(&key, plaintext, b"context");
+            assert_eq!(sealed.len(), plaintext.len() + SEAL_OVERHEAD);
+            assert_eq!(open(&key, &sealed, b"context").unwrap(), plaintext);
+        }
+
+        // Every message gets its own nonce
+        assert_ne!(seal(&key, b"hunter2", b""), seal(&key, b"hunter2", b""));
+    }
+
+    #[test]
+    fn flipped_bits_do_not_open() {
+        let key = Key::generate(&mut OsRng);
+        let sealed = seal(&key, b"hunter2", b"context");
+        // The nonce, the ciphertext and the tag
+        for index in 1..sealed.len() {
+            let mut flipped = sealed.clone();
+            flipped[index] ^= 1;
+            assert!(matches!(open(&key, &flipped, b"context"), Err(GaiaError::Unsealing)), "byte {index}");
+        }
+    }
+
+    #[test]
+    fn wrong_key_or_associated_data_does_not_open() {
+        let key = Key::generate(&mut OsRng);
+        let sealed = seal(&key, b"hunter2", b"context");
+        assert!(matches!(open(&Key::generate(&mut OsRng), &sealed, b"context"), Err(GaiaError::Unsealing)));
+        assert!(matches!(open(&key, &sealed, b"other context"), Err(GaiaError::Unsealing)));
+        assert!(matches!(open(&key, &sealed, b""), Err(GaiaError::Unsealing)));
+    }
+
+    #[test]
+    fn malformed_messages_are_told_apart() {
+        let key = Key::generate(&mut OsRng);
+        let sealed = seal(&key, b"", b"");
+        let mut unknown_version = sealed.clone();
+        unknown_version[0] = 2;
+        assert!(matches!(open(&key, &unknown_version, b""), Err(GaiaError::InvalidHeader(HeaderError::UnsupportedVersion(2)))));
+
+        for length in [0, 1, SEAL_OVERHEAD - 1] {
+            assert!(matches!(open(&key, &sealed[..length], b""), Err(GaiaError::InvalidHeader(HeaderError::Truncated))), "{length} bytes");
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Keeps a ciphertext while it is authenticated, so that it can be decrypted again afterwards
+//! without any plaintext having been released before the whole of it was checked, see [`Spool`].
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll, ready};
+use aead::OsRng;
+use rand_core::RngCore;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWriteExt, ReadBuf};
+
+/// Where verify-first decryption keeps the ciphertext between checking and decrypting it, see
+/// [`DecryptingReader::verify_first`](crate::DecryptingReader::verify_first).
+///
+/// The ciphertext is kept in memory until it outgrows the memory limit, and is moved to a file
+/// in the temporary directory after that, which is removed once the spool is dropped. Only the
+/// ciphertext is ever kept, so the file gives away no more than the input did.
+///
+/// `F` is the type of that file: a [`File`] for the synchronous readers, and a `tokio::fs::File`
+/// for the async ones, so that reading a spilled spool does not block the runtime.
+pub struct Spool<F = File> {
+    memory_limit: usize,
+    storage: Storage<F>,
+}
+
+enum Storage<F> {
+    Memory(Cursor<Vec<u8>>),
+    File(F, PathBuf),
+}
+
+impl<F> Spool<F> {
+    pub(crate) fn new(memory_limit: usize) -> Self {
+        Self { memory_limit, storage: Storage::Memory(Cursor::new(vec![])) }
+    }
+
+    /// Whether writing `length` more bytes would outgrow the memory limit, so that the ciphertext
+    /// has to be moved to a file first.
+    fn overflows(&self, length: usize) -> bool {
+        match self.storage {
+            Storage::Memory(ref memory) => memory.get_ref().len() + length > self.memory_limit,
+            Storage::File(..) => false,
+        }
+    }
+
+    /// A new path in the temporary directory to move the ciphertext to.
+    fn spill_path() -> PathBuf {
+        std::env::temp_dir().join(format!("gaia-{:016x}.spool", OsRng.next_u64()))
+    }
+}
+
+impl Spool {
+    /// Moves the ciphertext kept in memory to a new temporary file.
+    fn spill(&mut self) -> io::Result<()> {
+        let Storage::Memory(ref memory) = self.storage else {
+            return Ok(());
+        };
+
+        let path = Self::spill_path();
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        if let Err(e) = file.write_all(memory.get_ref()) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        self.storage = Storage::File(file, path);
+        Ok(())
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.storage {
+            Storage::Memory(ref mut memory) => memory.read(buffer),
+            Storage::File(ref mut file, _) => file.read(buffer),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.overflows(bytes.len()) {
+            self.spill()?;
+        }
+        match self.storage {
+            Storage::Memory(ref mut memory) => Write::write(memory, bytes),
+            Storage::File(ref mut file, _) => file.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.storage {
+            Storage::Memory(_) => Ok(()),
+            Storage::File(ref mut file, _) => file.flush(),
+        }
+    }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self.storage {
+            Storage::Memory(ref mut memory) => memory.seek(position),
+            Storage::File(ref mut file, _) => file.seek(position),
+        }
+    }
+}
+
+impl<F> Drop for Spool<F> {
+    fn drop(&mut self) {
+        if let Storage::File(_, ref path) = self.storage {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Spool<tokio::fs::File> {
+    /// Moves the ciphertext kept in memory to a new temporary file, like [`Spool::spill`].
+    async fn spill(&mut self) -> io::Result<()> {
+        let Storage::Memory(ref memory) = self.storage else {
+            return Ok(());
+        };
+
+        let path = Self::spill_path();
+        let mut file = tokio::fs::OpenOptions::new().read(true).write(true).create_new(true).open(&path).await?;
+        if let Err(e) = AsyncWriteExt::write_all(&mut file, memory.get_ref()).await {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e);
+        }
+        self.storage = Storage::File(file, path);
+        Ok(())
+    }
+
+    /// Writes all of `bytes`, moving the ciphertext to a file first if it outgrows the memory limit.
+    pub(crate) async fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.overflows(bytes.len()) {
+            self.spill().await?;
+        }
+        match self.storage {
+            Storage::Memory(ref mut memory) => Write::write_all(memory, bytes),
+            Storage::File(ref mut file, _) => AsyncWriteExt::write_all(file, bytes).await,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for Spool<tokio::fs::File> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().storage {
+            Storage::Memory(ref mut memory) => Pin::new(memory).poll_read(cx, output),
+            Storage::File(ref mut file, _) => Pin::new(file).poll_read(cx, output),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSeek for Spool<tokio::fs::File> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut().storage {
+            Storage::Memory(ref mut memory) => Pin::new(memory).start_seek(position),
+            Storage::File(ref mut file, _) => Pin::new(file).start_seek(position),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut().storage {
+            Storage::Memory(ref mut memory) => Pin::new(memory).poll_complete(cx),
+            Storage::File(ref mut file, _) => Pin::new(file).poll_complete(cx),
+        }
+    }
+}
+
+/// A reader that copies everything read from another reader into `copy`: a [`Spool`] for the
+/// synchronous readers, and a buffer that is moved to the spool between reads for the async ones.
+pub(crate) struct Tee<R, W> {
+    pub(crate) reader: R,
+    pub(crate) copy: W,
+}
+
+impl<R, W> Read for Tee<R, W> where R: Read, W: Write {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buffer)?;
+        self.copy.write_all(&buffer[..read])?;
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R> AsyncRead for Tee<R, Vec<u8>> where R: AsyncRead + Unpin {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let start = output.filled().len();
+        ready!(Pin::new(&mut this.reader).poll_read(cx, output))?;
+        this.copy.extend_from_slice(&output.filled()[start..]);
+        Poll::Ready(Ok(()))
+    }
+}
@@ -1,80 +1,751 @@
-use std::io::{self, Read, Write, BufRead, BufReader, ErrorKind};
-use std::ops::Sub;
-use aead::{AeadCore, OsRng, consts::U4, generic_array::ArrayLength};
-use crypto_common::typenum::Unsigned;
+use std::io::{self, Read, Write, ErrorKind, Seek, SeekFrom};
+use aead::OsRng;
 
-use crate::{error::GaiaError, BUF_SIZE, Stream, Cipher, Encryptor, Decryptor, StreamTagLength, generate_handle, Handle};
+use crate::{error::GaiaError, generate_handle, Handle, BUF_SIZE, DecryptOptions, EncryptOptions, Identity, Metadata, Recipient};
+use crate::options::Opened;
+use crate::algorithm::{plaintext_length, ChunkCipher, TAG_LENGTH};
+use crate::buffer::ChunkBuffer;
+use crate::encoding::{EncodingInput, PlaintextDecoder, PlaintextEncoder};
+use crate::header::{missing_preamble_bytes, preamble_length, HeaderError, HEADER_LENGTH};
+use crate::recipient::write_preamble;
+use crate::spool::{Spool, Tee};
 
 macro_rules! sync_crypt_reader_impl {
-    ($name: ident, $transform: ident, $next_in_place: ident, $last_in_place: ident, $buf_size_calc: expr) => {
+    ($name: ident, $setup: ty, $header: ident, $in_place: ident) => {
         pub struct $name<R> where R: Read {
-            reader: Box<BufReader<R>>,
-            transform: Option<$transform<Cipher, Stream>>,
-            input_buffer: Vec<u8>,
-            transform_buffer: Vec<u8>,
+            reader: R,
+            setup: $setup, // Whatever the header hook needs to set up the cipher
+            cipher: Option<ChunkCipher>, // Set up once the header has been handled
+            associated_data: Vec<u8>,
+            position: u32,
+            finished: bool,
+            chunk_length: usize, // Length of a single chunk of input, including the tag when decrypting
+            lookahead: Option<u8>, // First byte of the next chunk, read to tell whether there is one
+            buffer: ChunkBuffer, // The current chunk, transformed once it has been read completely
+            encoding: Option<EncodingInput>, // Compresses and pads the input when encrypting
+            decoder: Option<PlaintextDecoder>, // Decompresses or unpads the output when decrypting
+            read: u64, // Bytes read from the reader, which tells where the stream starts in it when seeking
         }
 
         impl<R> $name<R> where R: Read {
-            pub fn new(reader: R, (key, nonce): &Handle) -> Self {
-                let reader = BufReader::new(reader);
-                let transform = $transform::<Cipher, Stream>::new(key, nonce);
-                let buffer = Vec::new();
+            fn with_parameters(reader: R, setup: $setup, chunk_length: usize) -> Self {
+                Self { reader, setup, cipher: None, associated_data: vec![], position: 0, finished: false,
+                    chunk_length, lookahead: None, buffer: ChunkBuffer::new(), encoding: None, decoder: None, read: 0 }
+            }
+        }
+
+        impl<R> $name<R> where R: Read {
+            /// Reads and transforms the next chunk into the buffer, unless the last one has been read.
+            fn transform_chunk(&mut self) -> io::Result<()> {
+                let Some(cipher) = &self.cipher else {
+                    let cipher = self.$header()?;
+                    self.cipher = Some(cipher);
+                    return Ok(()); // Whatever the header hook buffered is read out first
+                };
 
-                Self { reader: Box::new(reader), transform: Some(transform), input_buffer: buffer, transform_buffer: vec![] }
+                if self.finished {
+                    return Ok(());
+                }
+
+                // Reading one byte past the chunk tells whether another chunk follows it
+                self.buffer.clear();
+                self.buffer.reserve(self.chunk_length + TAG_LENGTH);
+                if let Some(byte) = self.lookahead.take() {
+                    self.buffer.push(byte);
+                }
+                let end = self.chunk_length + 1;
+                while self.buffer.len() < end {
+                    let read = match self.encoding {
+                        Some(ref mut input) => input.read(&mut self.reader, self.buffer.unfilled(end)),
+                        None => self.reader.read(self.buffer.unfilled(end)),
+                    };
+                    match read {
+                        Ok(0) => break,
+                        Ok(length) => {
+                            self.buffer.fill(length);
+                            self.read += length as u64;
+                        }
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                if self.buffer.len() == end {
+                    self.lookahead = self.buffer.pop();
+                }
+
+                let last_chunk = self.lookahead.is_none();
+                cipher.$in_place(self.position, last_chunk, &self.associated_data, &mut self.buffer)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+                self.position += 1;
+                self.finished = last_chunk;
+                Ok(())
             }
         }
 
         impl<R> Read for $name<R> where R: Read {
             fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-                self.input_buffer.clear();
-                self.reader.as_mut().take($buf_size_calc as u64).read_to_end(&mut self.input_buffer)?;
-
-                if let Ok(more_data) = self.reader.has_data_left() && more_data {
-                    if let Some(ref mut transform) = self.transform.as_mut() {
-                        transform.$next_in_place(&[], &mut self.input_buffer)
-                            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
-                        self.transform_buffer.extend_from_slice(&self.input_buffer);
+                loop {
+                    while self.buffer.is_empty() && !self.finished {
+                        self.transform_chunk()?;
+                    }
+
+                    let Some(decoder) = self.decoder.as_mut().filter(|_| !output.is_empty()) else {
+                        let effective_length = output.len().min(self.buffer.len());
+                        output[..effective_length].copy_from_slice(&self.buffer.unread()[..effective_length]);
+                        self.buffer.consume(effective_length);
+                        return Ok(effective_length);
+                    };
+
+                    let (read, written) = decoder.decode(self.buffer.unread(), output)?;
+                    self.buffer.consume(read);
+                    if written > 0 {
+                        return Ok(written);
                     }
-                } else {
-                    if let Some(transform) = self.transform.take() {
-                        transform.$last_in_place(&[], &mut self.input_buffer)
-                            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
-                        self.transform_buffer.extend_from_slice(&self.input_buffer);
+                    if self.buffer.is_empty() && self.finished {
+                        decoder.finish()?;
+                        return Ok(0);
                     }
                 }
-
-                let effective_length = output.len().min(self.transform_buffer.len());
-                output.split_at_mut(effective_length).0.copy_from_slice(&self.transform_buffer[0..effective_length]);
-                self.transform_buffer.drain(0..effective_length);
-                Ok(effective_length)
             }
         }
     };
 }
 
-sync_crypt_reader_impl!(EncryptingReader, Encryptor, encrypt_next_in_place, encrypt_last_in_place, BUF_SIZE);
-sync_crypt_reader_impl!(DecryptingReader, Decryptor, decrypt_next_in_place, decrypt_last_in_place, BUF_SIZE + StreamTagLength::to_usize());
+sync_crypt_reader_impl!(EncryptingReader, (Handle, EncryptOptions), write_header, encrypt_in_place);
+sync_crypt_reader_impl!(DecryptingReader, Decryption, read_header, decrypt_in_place);
+
+/// What the decrypting reader needs besides the stream itself.
+struct Decryption {
+    options: DecryptOptions,
+    metadata: Option<Metadata>, // Read from the preamble
+    start: Option<u64>, // Where the stream starts in the reader, found when it is first seeked
+}
 
 impl<R> EncryptingReader<R> where R: Read {
+    pub fn new(reader: R, handle: &Handle) -> Self {
+        Self::with_options(reader, handle, &EncryptOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub fn with_options(reader: R, handle: &Handle, options: &EncryptOptions) -> Self {
+        assert_eq!(handle.nonce().len(), options.algorithm.nonce_length(), "the handle does not fit {}", options.algorithm);
+        Self { encoding: EncodingInput::new(options),
+            ..Self::with_parameters(reader, (handle.clone(), options.clone()), options.chunk_size) }
+    }
+
+    /// Encrypts with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(reader: R, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(reader, handle, &EncryptOptions::default().associated_data(associated_data))
+    }
+
     pub fn new_with_os_rng(reader: R) -> (Self, Handle) {
         let handle = generate_handle(&mut OsRng);
         (Self::new(reader, &handle), handle)
     }
+
+    fn write_header(&mut self) -> io::Result<ChunkCipher> {
+        let (handle, options) = &self.setup;
+        let preamble = write_preamble(handle, options)?;
+        self.buffer.reserve(preamble.len());
+        self.buffer.push_slice(&preamble);
+        self.associated_data = [preamble.as_slice(), &options.associated_data].concat();
+        Ok(ChunkCipher::new(options.algorithm, handle).expect("the handle was checked to fit the algorithm"))
+    }
+}
+
+impl<R> DecryptingReader<R> where R: Read {
+    pub fn new(reader: R, handle: &Handle) -> Self {
+        Self::with_identity(reader, Identity::Handle(handle.clone()))
+    }
+
+    /// Decrypts with any identity the stream was encrypted for, such as a passphrase.
+    pub fn with_identity(reader: R, identity: Identity) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identity(identity))
+    }
+
+    /// Decrypts with whichever of the identities the stream was encrypted for, trying them in order.
+    pub fn with_identities(reader: R, identities: impl IntoIterator<Item = Identity>) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identities(identities))
+    }
+
+    /// Decrypts a stream that was encrypted with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(reader: R, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(reader, &DecryptOptions::new().identity(Identity::Handle(handle.clone())).associated_data(associated_data))
+    }
+
+    pub fn with_options(reader: R, options: &DecryptOptions) -> Self {
+        // The chunk length is only known once the header has been read
+        Self::with_parameters(reader, Decryption { options: options.clone(), metadata: None, start: None }, 0)
+    }
+
+    /// Reads the preamble if it has not been read yet, and returns the metadata of the stream if
+    /// it has any, see [`EncryptOptions::metadata`].
+    pub fn metadata(&mut self) -> io::Result<Option<&Metadata>> {
+        if self.cipher.is_none() {
+            let cipher = self.read_header()?;
+            self.cipher = Some(cipher);
+        }
+        Ok(self.setup.metadata.as_ref())
+    }
+
+    fn read_header(&mut self) -> io::Result<ChunkCipher> {
+        let invalid_data = |e: HeaderError| io::Error::new(ErrorKind::InvalidData, e);
+
+        let mut preamble = Vec::with_capacity(HEADER_LENGTH);
+        loop {
+            let missing = missing_preamble_bytes(&preamble).map_err(invalid_data)?;
+            if missing == 0 {
+                break;
+            }
+
+            if self.reader.by_ref().take(missing as u64).read_to_end(&mut preamble)? < missing {
+                // Short foreign input is reported as such rather than as truncated
+                missing_preamble_bytes(&preamble).map_err(invalid_data)?;
+                return Err(invalid_data(HeaderError::Truncated));
+            }
+        }
+        self.read += preamble.len() as u64;
+
+        let Opened { cipher, chunk_length, associated_data, decoder, metadata } = self.setup.options.open(&preamble)?;
+        self.chunk_length = chunk_length;
+        self.associated_data = associated_data;
+        self.decoder = decoder;
+        self.setup.metadata = metadata;
+        Ok(cipher)
+    }
+}
+
+impl DecryptingReader<Spool> {
+    /// Reads and authenticates all of `input` before decrypting any of it, so that no plaintext is
+    /// released from a stream that turns out to be tampered with or cut short.
+    ///
+    /// The ciphertext is kept in a [`Spool`] meanwhile, in memory up to `memory_limit` bytes and in
+    /// a temporary file after that, and the returned reader decrypts it again from there. Seekable
+    /// input can be read twice instead, see [`verify_seekable`](DecryptingReader::verify_seekable).
+    pub fn verify_first(input: impl Read, options: &DecryptOptions, memory_limit: usize) -> io::Result<Self> {
+        let mut spool = Spool::new(memory_limit);
+        io::copy(&mut DecryptingReader::with_options(Tee { reader: input, copy: &mut spool }, options), &mut io::sink())?;
+        spool.rewind()?;
+        Ok(Self::with_options(spool, options))
+    }
 }
 
-pub fn encrypt(input: impl Read, mut output: impl Write) -> Result<Handle, GaiaError> {
-    let handle = generate_handle(&mut OsRng);
-    let mut reader = EncryptingReader::new(Box::new(input), &handle);
-    io::copy(&mut reader, &mut output).map_err(|e| GaiaError::WritingOutput(e))?;
+impl<R> DecryptingReader<R> where R: Read + Seek {
+    /// Like [`verify_first`](DecryptingReader::verify_first), but reads all of `input` to
+    /// authenticate it and then seeks back to where it started, instead of keeping the ciphertext.
+    pub fn verify_seekable(mut input: R, options: &DecryptOptions) -> io::Result<Self> {
+        let start = input.stream_position()?;
+        io::copy(&mut DecryptingReader::with_options(&mut input, options), &mut io::sink())?;
+        input.seek(SeekFrom::Start(start))?;
+        Ok(Self::with_options(input, options))
+    }
+}
+
+/// Seeks to a position in the plaintext by decrypting only the chunk it is in.
+///
+/// Seeking to or past the end of the plaintext decrypts the last chunk all the same, so that a
+/// truncated stream fails to seek instead of seeming to end early. Compressed or padded plaintext
+/// cannot be seeked.
+impl<R> Seek for DecryptingReader<R> where R: Read + Seek {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        if self.cipher.is_none() {
+            let cipher = self.read_header()?;
+            self.cipher = Some(cipher);
+        }
+        if self.decoder.is_some() {
+            return Err(io::Error::new(ErrorKind::Unsupported, "compressed or padded plaintext cannot be seeked"));
+        }
+
+        // The stream need not start at the start of the reader, like when it follows something else in a file
+        let start = match self.setup.start {
+            Some(start) => start,
+            None => *self.setup.start.insert(self.reader.stream_position()? - self.read),
+        };
+        let chunks_start = start + (self.associated_data.len() - self.setup.options.associated_data.len()) as u64;
+        let chunk_size = (self.chunk_length - TAG_LENGTH) as u64;
+        let chunks_length = self.reader.seek(SeekFrom::End(0))? - chunks_start;
+        let total = plaintext_length(chunks_length, chunk_size);
+
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => total.checked_add_signed(offset),
+            // Every chunk before the last one is full, and the unread plaintext is still buffered
+            SeekFrom::Current(offset) => match self.finished {
+                true => total - self.buffer.len() as u64,
+                false => self.position as u64 * chunk_size - self.buffer.len() as u64,
+            }.checked_add_signed(offset),
+        }.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))?;
+
+        self.buffer.clear();
+        self.lookahead = None;
+
+        // Seeking to or past the end still authenticates the last chunk, so that truncation is noticed
+        let index = match target >= total {
+            true => chunks_length.saturating_sub(1) / self.chunk_length as u64,
+            false => target / chunk_size,
+        };
+        self.position = u32::try_from(index).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "seek past the last possible chunk"))?;
+        self.finished = false;
+        self.reader.seek(SeekFrom::Start(chunks_start + index * self.chunk_length as u64))?;
+        self.read = chunks_start + index * self.chunk_length as u64 - start;
+
+        // Decrypt the chunk the target is in and drop whatever precedes the target
+        self.transform_chunk()?;
+        self.buffer.consume((target.min(total) - index * chunk_size) as usize);
+        Ok(target)
+    }
+}
+
+macro_rules! sync_crypt_writer_impl {
+    ($name: ident, $setup: ty, $header: ident, $in_place: ident) => {
+        /// The output is only complete once [`finish`](Self::finish) has been called, as the last chunk
+        /// is not known until then. Dropping the writer without finishing it leaves the output truncated.
+        pub struct $name<W> where W: Write {
+            writer: W,
+            setup: $setup, // Whatever the header hook needs to set up the cipher
+            cipher: Option<ChunkCipher>, // Set up once the header has been handled
+            associated_data: Vec<u8>,
+            position: u32,
+            chunk_length: usize, // Length of a single chunk of input, including the tag when decrypting
+            input_buffer: ChunkBuffer, // Input that has not been transformed yet
+            chunk_buffer: ChunkBuffer,
+            encoded: Vec<u8>, // What the encoder made of the latest input, before it is buffered
+            encoder: Option<PlaintextEncoder>, // Compresses and pads the input when encrypting
+            decoder: Option<PlaintextDecoder>, // Decompresses or unpads the output when decrypting
+        }
+
+        impl<W> $name<W> where W: Write {
+            fn with_parameters(writer: W, setup: $setup, chunk_length: usize) -> Self {
+                Self { writer, setup, cipher: None, associated_data: vec![], position: 0,
+                    chunk_length, input_buffer: ChunkBuffer::new(), chunk_buffer: ChunkBuffer::new(), encoded: vec![],
+                    encoder: None, decoder: None }
+            }
+
+            /// Buffers `input`, encoding it first if there is an encoder.
+            fn buffer_input(&mut self, input: &[u8]) -> io::Result<()> {
+                match self.encoder {
+                    Some(ref mut encoder) => {
+                        self.encoded.clear();
+                        encoder.encode(input, &mut self.encoded)?;
+                        self.input_buffer.append(&self.encoded);
+                    }
+                    None => self.input_buffer.append(input),
+                }
+                Ok(())
+            }
+
+            /// Transforms and writes every buffered chunk that more input is known to follow, and
+            /// the rest as the last chunk when finishing.
+            fn transform_chunks(&mut self, finishing: bool) -> io::Result<()> {
+                let cipher = match self.cipher {
+                    Some(ref cipher) => cipher,
+                    None => match self.$header(finishing)? {
+                        Some(cipher) => self.cipher.insert(cipher),
+                        None => return Ok(()), // The header is not complete yet
+                    }
+                };
+
+                while self.input_buffer.len() > self.chunk_length || finishing {
+                    let length = self.chunk_length.min(self.input_buffer.len());
+                    let last_chunk = finishing && length == self.input_buffer.len();
+
+                    self.chunk_buffer.clear();
+                    self.chunk_buffer.reserve(self.chunk_length + TAG_LENGTH);
+                    self.chunk_buffer.push_slice(&self.input_buffer.unread()[..length]);
+                    self.input_buffer.consume(length);
+                    cipher.$in_place(self.position, last_chunk, &self.associated_data, &mut self.chunk_buffer)
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                    match self.decoder {
+                        Some(ref mut decoder) => decoder.decode_into(self.chunk_buffer.unread(), &mut self.writer)?,
+                        None => self.writer.write_all(self.chunk_buffer.unread())?,
+                    }
+                    self.position += 1;
+
+                    if last_chunk {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+
+            /// Transforms and writes the last chunk, flushes the underlying writer and returns it.
+            pub fn finish(mut self) -> io::Result<W> {
+                if let Some(encoder) = self.encoder.take() {
+                    // The padding is added a chunk at a time, as there can be a lot of it
+                    self.encoded.clear();
+                    let mut tail = encoder.finish(&mut self.encoded)?;
+                    self.input_buffer.append(&self.encoded);
+                    while !tail.is_empty() {
+                        self.encoded.clear();
+                        tail.fill(&mut self.encoded, self.chunk_length);
+                        self.input_buffer.append(&self.encoded);
+                        self.transform_chunks(false)?;
+                    }
+                }
+                self.transform_chunks(true)?;
+                if let Some(ref decoder) = self.decoder {
+                    decoder.finish()?;
+                }
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+
+        impl<W> Write for $name<W> where W: Write {
+            fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+                self.buffer_input(input)?;
+                self.transform_chunks(false)?;
+                Ok(input.len())
+            }
+
+            /// Flushes the underlying writer. Input that is not yet known to be followed by more input
+            /// stays buffered, as it may be the last chunk.
+            fn flush(&mut self) -> io::Result<()> {
+                self.writer.flush()
+            }
+        }
+    };
+}
+
+sync_crypt_writer_impl!(EncryptingWriter, (Handle, EncryptOptions), write_header, encrypt_in_place);
+sync_crypt_writer_impl!(DecryptingWriter, DecryptOptions, read_header, decrypt_in_place);
+
+impl<W> EncryptingWriter<W> where W: Write {
+    pub fn new(writer: W, handle: &Handle) -> Self {
+        Self::with_options(writer, handle, &EncryptOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub fn with_options(writer: W, handle: &Handle, options: &EncryptOptions) -> Self {
+        assert_eq!(handle.nonce().len(), options.algorithm.nonce_length(), "the handle does not fit {}", options.algorithm);
+        Self { encoder: PlaintextEncoder::new(options),
+            ..Self::with_parameters(writer, (handle.clone(), options.clone()), options.chunk_size) }
+    }
+
+    /// Encrypts with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(writer: W, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(writer, handle, &EncryptOptions::default().associated_data(associated_data))
+    }
+
+    pub fn new_with_os_rng(writer: W) -> (Self, Handle) {
+        let handle = generate_handle(&mut OsRng);
+        (Self::new(writer, &handle), handle)
+    }
+
+    fn write_header(&mut self, _finishing: bool) -> io::Result<Option<ChunkCipher>> {
+        let (handle, options) = &self.setup;
+        let preamble = write_preamble(handle, options)?;
+        self.writer.write_all(&preamble)?;
+        self.associated_data = [preamble.as_slice(), &options.associated_data].concat();
+        Ok(Some(ChunkCipher::new(options.algorithm, handle).expect("the handle was checked to fit the algorithm")))
+    }
+}
+
+impl<W> DecryptingWriter<W> where W: Write {
+    pub fn new(writer: W, handle: &Handle) -> Self {
+        Self::with_identity(writer, Identity::Handle(handle.clone()))
+    }
+
+    /// Decrypts with any identity the stream was encrypted for, such as a passphrase.
+    pub fn with_identity(writer: W, identity: Identity) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identity(identity))
+    }
+
+    /// Decrypts with whichever of the identities the stream was encrypted for, trying them in order.
+    pub fn with_identities(writer: W, identities: impl IntoIterator<Item = Identity>) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identities(identities))
+    }
+
+    /// Decrypts a stream that was encrypted with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(writer: W, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identity(Identity::Handle(handle.clone())).associated_data(associated_data))
+    }
+
+    pub fn with_options(writer: W, options: &DecryptOptions) -> Self {
+        // The chunk length is only known once the header has been read
+        Self::with_parameters(writer, options.clone(), 0)
+    }
+
+    fn read_header(&mut self, finishing: bool) -> io::Result<Option<ChunkCipher>> {
+        let invalid_data = |e: HeaderError| io::Error::new(ErrorKind::InvalidData, e);
+
+        let Some(length) = preamble_length(self.input_buffer.unread()).map_err(invalid_data)? else {
+            return match finishing {
+                true => Err(invalid_data(HeaderError::Truncated)),
+                false => Ok(None),
+            };
+        };
+
+        let Opened { cipher, chunk_length, associated_data, decoder, .. } = self.setup.open(&self.input_buffer.unread()[..length])?;
+        self.input_buffer.consume(length);
+        self.chunk_length = chunk_length;
+        self.associated_data = associated_data;
+        self.decoder = decoder;
+        Ok(Some(cipher))
+    }
+}
+
+pub fn encrypt(input: impl Read, output: impl Write) -> Result<Handle, GaiaError> {
+    encrypt_with_options(input, output, &EncryptOptions::default())
+}
+
+pub fn encrypt_with_options(input: impl Read, mut output: impl Write, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let handle = Handle::generate(options.algorithm, &mut OsRng);
+    let mut reader = EncryptingReader::with_options(Box::new(input), &handle, options);
+    copy(&mut reader, &mut output)?;
     Ok(handle)
 }
 
-pub fn decrypt(input: impl Read, handle: &Handle, mut output: impl Write) -> Result<(), GaiaError>
-    where
-        <Cipher as AeadCore>::NonceSize: Sub<U4>,
-        <<Cipher as AeadCore>::NonceSize as Sub<U4>>::Output: ArrayLength<u8>
-{
-    let mut reader = DecryptingReader::new(Box::new(input), handle);
-    io::copy(&mut reader, &mut output).map_err(|e| GaiaError::WritingOutput(e))?;
-    Ok(())
-}
\ No newline at end of file
+pub fn encrypt_for(input: impl Read, output: impl Write, recipients: &[Recipient]) -> Result<Handle, GaiaError> {
+    encrypt_with_options(input, output, &EncryptOptions::default().recipients(recipients.iter().cloned()))
+}
+
+pub fn decrypt(input: impl Read, handle: &Handle, output: impl Write) -> Result<(), GaiaError> {
+    decrypt_with_identity(input, &Identity::Handle(handle.clone()), output)
+}
+
+pub fn decrypt_with_identity(input: impl Read, identity: &Identity, output: impl Write) -> Result<(), GaiaError> {
+    decrypt_with_identities(input, std::slice::from_ref(identity), output)
+}
+
+pub fn decrypt_with_identities(input: impl Read, identities: &[Identity], output: impl Write) -> Result<(), GaiaError> {
+    decrypt_with_options(input, &DecryptOptions::new().identities(identities.iter().cloned()), output)
+}
+
+pub fn decrypt_with_options(input: impl Read, options: &DecryptOptions, mut output: impl Write) -> Result<(), GaiaError> {
+    let mut reader = DecryptingReader::with_options(Box::new(input), options);
+    copy(&mut reader, &mut output)
+}
+
+/// Authenticates every chunk of `input`, telling apart a truncated stream from a complete one,
+/// without writing the plaintext anywhere.
+pub fn verify(input: impl Read, handle: &Handle) -> Result<(), GaiaError> {
+    verify_with_options(input, &DecryptOptions::new().identity(Identity::Handle(handle.clone())))
+}
+
+pub fn verify_with_options(input: impl Read, options: &DecryptOptions) -> Result<(), GaiaError> {
+    decrypt_with_options(input, options, io::sink())
+}
+
+/// Copies all of `reader` into `output` and flushes it, telling the errors of the two apart.
+fn copy(reader: &mut impl Read, output: &mut impl Write) -> Result<(), GaiaError> {
+    let mut buffer = vec![0u8; BUF_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return output.flush().map_err(GaiaError::WritingOutput),
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(GaiaError::from_input_error(e)),
+        };
+        output.write_all(&buffer[..read]).map_err(GaiaError::WritingOutput)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+    use crate::{decrypt, generate_handle, DecryptOptions, DecryptingReader, DecryptingWriter, Identity, encrypt_with_options, EncryptOptions, EncryptingReader, EncryptingWriter, Handle, Padding};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+    use crate::header::{inspect, HeaderError, HEADER_LENGTH};
+
+    const CHUNK_SIZE: usize = 64;
+
+    fn plaintext(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    fn encrypted(plaintext: &[u8]) -> (Vec<u8>, Handle) {
+        let mut ciphertext = vec![];
+        let handle = encrypt_with_options(plaintext, &mut ciphertext, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).unwrap();
+        (ciphertext, handle)
+    }
+
+    fn decrypted(ciphertext: &[u8], handle: &Handle) -> Result<Vec<u8>, GaiaError> {
+        let mut plaintext = vec![];
+        decrypt(ciphertext, handle, &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn round_trip() {
+        for length in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE] {
+            let (ciphertext, handle) = encrypted(&plaintext(length));
+            assert_eq!(decrypted(&ciphertext, &handle).unwrap(), plaintext(length), "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn empty_input_ends_with_a_final_chunk() {
+        let (ciphertext, _) = encrypted(&[]);
+        let layout = inspect(ciphertext.as_slice()).unwrap();
+        assert_eq!((layout.full_chunks, layout.final_chunk_length), (0, Some(TAG_LENGTH as u64)));
+    }
+
+    #[test]
+    fn empty_and_truncated_ciphertext_is_rejected() {
+        let (ciphertext, handle) = encrypted(&[]);
+        assert!(matches!(decrypted(&[], &handle), Err(GaiaError::InvalidHeader(HeaderError::Truncated))));
+        assert!(matches!(decrypted(&ciphertext[..HEADER_LENGTH - 1], &handle), Err(GaiaError::InvalidHeader(HeaderError::Truncated))));
+        for length in [ciphertext.len() - TAG_LENGTH, ciphertext.len() - 1] {
+            assert!(decrypted(&ciphertext[..length], &handle).is_err(), "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn stream_errors_are_classified() {
+        let (ciphertext, handle) = encrypted(&plaintext(3 * CHUNK_SIZE));
+        let chunk_length = CHUNK_SIZE + TAG_LENGTH;
+        let chunks_start = ciphertext.len() - 3 * chunk_length;
+
+        let mut tampered = ciphertext.clone();
+        tampered[chunks_start + chunk_length] ^= 1;
+        let offset = (chunks_start + chunk_length) as u64;
+        assert!(matches!(decrypted(&tampered, &handle), Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 1, offset: o })) if o == offset));
+
+        let offset = (ciphertext.len() - chunk_length) as u64;
+        assert!(matches!(decrypted(&ciphertext[..offset as usize], &handle), Err(GaiaError::Decrypting(StreamError::Truncated { offset: o })) if o == offset));
+
+        let extended = [ciphertext.as_slice(), &[0]].concat();
+        let offset = ciphertext.len() as u64;
+        assert!(matches!(decrypted(&extended, &handle), Err(GaiaError::Decrypting(StreamError::TrailingData { offset: o })) if o == offset));
+    }
+
+    #[test]
+    fn changes_to_a_short_last_chunk_do_not_authenticate() {
+        let (ciphertext, handle) = encrypted(&plaintext(3 * CHUNK_SIZE + 10));
+        let offset = (ciphertext.len() - 10 - TAG_LENGTH) as u64;
+        for changed in [&ciphertext[..ciphertext.len() - 1], &[ciphertext.as_slice(), &[0]].concat()] {
+            assert!(matches!(decrypted(changed, &handle), Err(GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 3, offset: o })) if o == offset));
+        }
+    }
+
+    #[test]
+    fn io_errors_are_told_apart() {
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("failing"))
+            }
+        }
+
+        impl Write for Failing {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("failing"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (ciphertext, handle) = encrypted(&plaintext(CHUNK_SIZE));
+        assert!(matches!(decrypt(Failing, &handle, &mut vec![]), Err(GaiaError::ReadingInput(_))));
+        assert!(matches!(decrypt(ciphertext.as_slice(), &handle, Failing), Err(GaiaError::WritingOutput(_))));
+        assert!(matches!(encrypt_with_options(Failing, vec![], &EncryptOptions::new()), Err(GaiaError::ReadingInput(_))));
+    }
+
+    #[test]
+    fn seek_lands_in_the_right_chunk() {
+        let plaintext = plaintext(3 * CHUNK_SIZE + 10);
+        let (ciphertext, handle) = encrypted(&plaintext);
+
+        // The stream does not have to start at the beginning of its reader
+        let mut input = Cursor::new([b"prefix".as_slice(), &ciphertext].concat());
+        input.set_position(6);
+        let mut reader = DecryptingReader::new(input, &handle);
+        for position in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, 2 * CHUNK_SIZE + 5, 3 * CHUNK_SIZE + 9, 3 * CHUNK_SIZE + 10] {
+            assert_eq!(reader.seek(SeekFrom::Start(position as u64)).unwrap(), position as u64);
+            let mut rest = vec![];
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, &plaintext[position..], "from {position}");
+        }
+
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), plaintext.len() as u64 - 3);
+        reader.seek(SeekFrom::Current(-(CHUNK_SIZE as i64))).unwrap();
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], plaintext[plaintext.len() - 3 - CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn seek_fails_on_truncated_stream() {
+        let (ciphertext, handle) = encrypted(&plaintext(3 * CHUNK_SIZE + 10));
+        let truncated = &ciphertext[..ciphertext.len() - 10 - TAG_LENGTH];
+        let mut reader = DecryptingReader::new(Cursor::new(truncated), &handle);
+        assert!(reader.seek(SeekFrom::End(0)).is_err());
+        assert!(reader.seek(SeekFrom::Start(3 * CHUNK_SIZE as u64)).is_err());
+    }
+
+    #[test]
+    fn verify_first_never_releases_plaintext() {
+        let plaintext = plaintext(10 * CHUNK_SIZE);
+        let (ciphertext, handle) = encrypted(&plaintext);
+        let options = DecryptOptions::new().identity(Identity::Handle(handle));
+
+        // Spooled in memory, and spilled into a temporary file
+        for memory_limit in [usize::MAX, CHUNK_SIZE] {
+            let mut decrypted = vec![];
+            DecryptingReader::verify_first(ciphertext.as_slice(), &options, memory_limit).unwrap().read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut tampered = ciphertext.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(DecryptingReader::verify_first(tampered.as_slice(), &options, memory_limit).is_err());
+            assert!(DecryptingReader::verify_first(&ciphertext[..ciphertext.len() - 1], &options, memory_limit).is_err());
+        }
+
+        let mut decrypted = vec![];
+        DecryptingReader::verify_seekable(Cursor::new(&ciphertext), &options).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+        assert!(DecryptingReader::verify_seekable(Cursor::new(&ciphertext[..ciphertext.len() - 1]), &options).is_err());
+    }
+
+    /// Splits `bytes` into pieces of uneven lengths, some shorter and some longer than a chunk.
+    fn pieces(bytes: &[u8]) -> Vec<&[u8]> {
+        let mut lengths = [1, 7, CHUNK_SIZE, 3 * CHUNK_SIZE + 5, 0, CHUNK_SIZE - 1].into_iter().cycle();
+        let mut rest = bytes;
+        let mut pieces = vec![];
+        while !rest.is_empty() {
+            let (piece, tail) = rest.split_at(lengths.next().unwrap().min(rest.len()));
+            pieces.push(piece);
+            rest = tail;
+        }
+        pieces
+    }
+
+    #[test]
+    fn writers_match_the_readers() {
+        let plaintext = plaintext(10 * CHUNK_SIZE + 10);
+        for padding in [Padding::None, Padding::Padme] {
+            let options = EncryptOptions::new().chunk_size(CHUNK_SIZE).padding(padding);
+            let handle = generate_handle(&mut aead::OsRng);
+            let mut expected = vec![];
+            EncryptingReader::with_options(plaintext.as_slice(), &handle, &options).read_to_end(&mut expected).unwrap();
+
+            let mut writer = EncryptingWriter::with_options(vec![], &handle, &options);
+            for piece in pieces(&plaintext) {
+                writer.write_all(piece).unwrap();
+            }
+            let ciphertext = writer.finish().unwrap();
+            assert_eq!(ciphertext, expected, "{padding:?}");
+
+            let mut writer = DecryptingWriter::new(vec![], &handle);
+            for piece in pieces(&ciphertext) {
+                writer.write_all(piece).unwrap();
+            }
+            assert_eq!(writer.finish().unwrap(), plaintext, "{padding:?}");
+
+            let mut writer = DecryptingWriter::new(vec![], &handle);
+            writer.write_all(&ciphertext[..ciphertext.len() - 1]).unwrap();
+            assert!(writer.finish().is_err(), "{padding:?}");
+        }
+    }
+}
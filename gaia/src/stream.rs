@@ -0,0 +1,178 @@
+//! Encrypts and decrypts streams of [`Bytes`], such as the bodies of HTTP requests and responses.
+
+use std::error::Error;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+
+use crate::{error::GaiaError, DecryptOptions, EncryptOptions, Handle, Identity};
+use crate::chunker::{ChunkDecrypter, ChunkEncrypter, Chunker};
+
+/// Encrypts a stream of plaintext into a stream of ciphertext, one chunk per item, see [`encrypt_stream_with_options`].
+pub fn encrypt_stream<S, E>(stream: S, handle: &Handle) -> impl Stream<Item = Result<Bytes, GaiaError>> + use<S, E>
+where S: Stream<Item = Result<Bytes, E>>, E: Into<Box<dyn Error + Send + Sync>> {
+    encrypt_stream_with_options(stream, handle, &EncryptOptions::default())
+}
+
+/// Encrypts a stream of plaintext into a stream of ciphertext, one chunk per item. The preamble
+/// is part of the first item. The output is the same as that of [`encrypt_with_options`](crate::encrypt_with_options).
+///
+/// # Panics
+///
+/// Panics if the handle was not generated for the algorithm in the options.
+pub fn encrypt_stream_with_options<S, E>(stream: S, handle: &Handle, options: &EncryptOptions) -> impl Stream<Item = Result<Bytes, GaiaError>> + use<S, E>
+where S: Stream<Item = Result<Bytes, E>>, E: Into<Box<dyn Error + Send + Sync>> {
+    ChunkStream::new(stream, ChunkEncrypter::new(handle, options))
+}
+
+/// Decrypts a stream of ciphertext into a stream of plaintext, one chunk per item, see [`decrypt_stream_with_options`].
+pub fn decrypt_stream<S, E>(stream: S, handle: &Handle) -> impl Stream<Item = Result<Bytes, GaiaError>> + use<S, E>
+where S: Stream<Item = Result<Bytes, E>>, E: Into<Box<dyn Error + Send + Sync>> {
+    decrypt_stream_with_options(stream, &DecryptOptions::new().identity(Identity::Handle(handle.clone())))
+}
+
+/// Decrypts a stream of ciphertext into a stream of plaintext, one chunk per item. The items of
+/// the input do not need to line up with the chunks.
+///
+/// A chunk is only emitted once it has been authenticated, but the stream as a whole is only
+/// authenticated once it has ended without an error.
+pub fn decrypt_stream_with_options<S, E>(stream: S, options: &DecryptOptions) -> impl Stream<Item = Result<Bytes, GaiaError>> + use<S, E>
+where S: Stream<Item = Result<Bytes, E>>, E: Into<Box<dyn Error + Send + Sync>> {
+    ChunkStream::new(stream, ChunkDecrypter::new(options))
+}
+
+/// Collects the items of a stream into chunks and transforms them.
+struct ChunkStream<S, C> {
+    stream: Pin<Box<S>>,
+    chunker: C,
+    input: BytesMut, // Items of the stream that have not been transformed yet
+    at_end: bool, // Whether the stream has ended
+    failed: bool, // Whether an error has been emitted, which ends the stream
+}
+
+impl<S, C> ChunkStream<S, C> {
+    fn new(stream: S, chunker: C) -> Self {
+        Self { stream: Box::pin(stream), chunker, input: BytesMut::new(), at_end: false, failed: false }
+    }
+}
+
+impl<S, E, C> Stream for ChunkStream<S, C>
+where S: Stream<Item = Result<Bytes, E>>, E: Into<Box<dyn Error + Send + Sync>>, C: Chunker + Unpin {
+    type Item = Result<Bytes, GaiaError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.failed {
+            match this.chunker.next_chunk(&mut this.input, this.at_end) {
+                Ok(Some(chunk)) => return Poll::Ready(Some(Ok(chunk))),
+                Ok(None) if this.at_end => break,
+                Ok(None) => {}
+                Err(e) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(bytes)) => {
+                    if let Err(e) = this.chunker.push(&mut this.input, &bytes) {
+                        this.failed = true;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                Some(Err(e)) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(GaiaError::ReadingInput(io::Error::other(e)))));
+                }
+                None => this.at_end = true,
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use bytes::Bytes;
+    use futures_executor::block_on;
+    use futures_util::{stream, StreamExt};
+
+    use crate::{generate_handle, EncryptOptions, EncryptingReader, Handle};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::error::{GaiaError, StreamError};
+    use super::{decrypt_stream, encrypt_stream_with_options};
+
+    const CHUNK_SIZE: usize = 64;
+
+    /// Splits `bytes` into items of uneven lengths, some of them empty.
+    fn items(bytes: &[u8]) -> Vec<Result<Bytes, io::Error>> {
+        let mut items = vec![];
+        let mut rest = bytes;
+        for length in [0, 1, 13, 0, CHUNK_SIZE, 100].into_iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (item, tail) = rest.split_at(length.min(rest.len()));
+            items.push(Ok(Bytes::copy_from_slice(item)));
+            rest = tail;
+        }
+        items
+    }
+
+    fn collect(stream: impl futures_core::Stream<Item = Result<Bytes, GaiaError>>) -> Vec<Result<Bytes, GaiaError>> {
+        block_on(stream.collect())
+    }
+
+    fn encrypted(plaintext: &[u8], handle: &Handle, options: &EncryptOptions) -> Vec<u8> {
+        let chunks = collect(encrypt_stream_with_options(stream::iter(items(plaintext)), handle, options));
+        chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>().concat()
+    }
+
+    #[test]
+    fn output_is_identical_to_encrypt_with_options() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let options = EncryptOptions::new().chunk_size(CHUNK_SIZE);
+        for length in [0, 1, CHUNK_SIZE, 5 * CHUNK_SIZE + 7] {
+            let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let mut streamed = vec![];
+            EncryptingReader::with_options(plaintext.as_slice(), &handle, &options).read_to_end(&mut streamed).unwrap();
+            assert_eq!(encrypted(&plaintext, &handle, &options), streamed, "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn round_trip_one_chunk_per_item() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let plaintext: Vec<u8> = (0..5 * CHUNK_SIZE + 7).map(|i| i as u8).collect();
+        let ciphertext = encrypted(&plaintext, &handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE));
+
+        let chunks: Vec<Bytes> = collect(decrypt_stream(stream::iter(items(&ciphertext)), &handle)).into_iter().map(Result::unwrap).collect();
+        assert_eq!(chunks.len(), 6);
+        assert_eq!(chunks.concat(), plaintext);
+
+        let empty = encrypted(&[], &handle, &EncryptOptions::new());
+        let chunks = collect(decrypt_stream(stream::iter(items(&empty)), &handle));
+        assert!(chunks.iter().all(|chunk| chunk.as_ref().is_ok_and(Bytes::is_empty)));
+    }
+
+    #[test]
+    fn damaged_streams_end_with_their_error() {
+        let handle = generate_handle(&mut aead::OsRng);
+        let ciphertext = encrypted(&[7; 3 * CHUNK_SIZE], &handle, &EncryptOptions::new().chunk_size(CHUNK_SIZE));
+        let last_error = |input: &[u8]| collect(decrypt_stream(stream::iter(items(input)), &handle)).pop().unwrap().unwrap_err();
+
+        let end = ciphertext.len() - CHUNK_SIZE - TAG_LENGTH;
+        assert!(matches!(last_error(&ciphertext[..end]), GaiaError::Decrypting(StreamError::Truncated { offset }) if offset == end as u64));
+        let extended = [ciphertext.as_slice(), b"more"].concat();
+        assert!(matches!(last_error(&extended), GaiaError::Decrypting(StreamError::TrailingData { offset }) if offset == ciphertext.len() as u64));
+
+        // An error of the input ends the output too
+        let failing = stream::iter(vec![Ok(Bytes::copy_from_slice(&ciphertext[..20])), Err(io::Error::other("failing"))]);
+        let chunks = collect(decrypt_stream(failing, &handle));
+        assert!(matches!(chunks.as_slice(), [Err(GaiaError::ReadingInput(_))]));
+    }
+}
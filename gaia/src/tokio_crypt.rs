@@ -1,178 +1,488 @@
-use std::io::{self, ErrorKind};
-use std::ops::Sub;
+use std::io::{self, ErrorKind, SeekFrom};
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
-use aead::{AeadCore, consts::U4, generic_array::ArrayLength, OsRng};
-use crypto_common::typenum::Unsigned;
-use tokio::io::{AsyncRead, ReadBuf};
-
-use crate::{BUF_SIZE, Cipher, Decryptor, Encryptor, error::GaiaError, generate_handle, Handle, Stream, StreamTagLength};
+use aead::OsRng;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{error::GaiaError, generate_handle, Handle, BUF_SIZE, DecryptOptions, EncryptOptions, Identity, Recipient};
+use crate::algorithm::{ChunkCipher, TAG_LENGTH};
+use crate::buffer::ChunkBuffer;
+use crate::encoding::{PlaintextDecoder, PlaintextEncoder};
+use crate::options::Opened;
+use crate::padding::PaddingTail;
+use crate::async_crypt::{AsyncDecryptingReader, AsyncEncryptingReader, PollSource, SeekSource};
+use crate::header::{preamble_length, HeaderError};
+use crate::recipient::write_preamble;
+use crate::spool::{Spool, Tee};
+
+pub async fn encrypt_async(input: impl AsyncRead + Unpin, output: impl tokio::io::AsyncWrite + Unpin) -> Result<Handle, GaiaError> {
+    encrypt_async_with_options(input, output, &EncryptOptions::default()).await
+}
 
-pub async fn encrypt_async(input: impl AsyncRead + Unpin, mut output: impl tokio::io::AsyncWrite + Unpin) -> Result<Handle, GaiaError> {
-    let handle = generate_handle(&mut OsRng);
-    let mut reader = AsyncEncryptingReader::new(Box::new(input), &handle);
-    tokio::io::copy(&mut reader, &mut output).await.map_err(|e| GaiaError::WritingOutput(e))?;
+pub async fn encrypt_async_with_options(input: impl AsyncRead + Unpin, mut output: impl tokio::io::AsyncWrite + Unpin, options: &EncryptOptions) -> Result<Handle, GaiaError> {
+    let handle = Handle::generate(options.algorithm, &mut OsRng);
+    let mut reader = AsyncEncryptingReader::with_options(Box::new(input), &handle, options);
+    copy(&mut reader, &mut output).await?;
     Ok(handle)
 }
 
-pub async fn decrypt_async(input: impl AsyncRead + Unpin, handle: &Handle, mut output: impl tokio::io::AsyncWrite + Unpin) -> Result<(), GaiaError>
-    where
-        <Cipher as AeadCore>::NonceSize: Sub<U4>,
-        <<Cipher as AeadCore>::NonceSize as Sub<U4>>::Output: ArrayLength<u8>
-{
-    let mut reader = AsyncDecryptingReader::new(Box::new(input), handle);
-    tokio::io::copy(&mut reader, &mut output).await.map_err(|e| GaiaError::WritingOutput(e))?;
-    Ok(())
+pub async fn encrypt_async_for(input: impl AsyncRead + Unpin, output: impl tokio::io::AsyncWrite + Unpin, recipients: &[Recipient]) -> Result<Handle, GaiaError> {
+    encrypt_async_with_options(input, output, &EncryptOptions::default().recipients(recipients.iter().cloned())).await
 }
 
-/// Async reader that always fills the requested buffer completely, unless EOF is reached.
-///
-/// Also included is the ability to check whether the previously read chunk was the last one.
-/// Note that `was_last_chunk()` may return `false` when there are no chunks to read, i.e. the
-/// original reader was empty to begin with.
-pub struct AsyncChunkingReader<R> where R: AsyncRead + Unpin {
-    reader: Box<R>,
-    cursor: usize, // Tracks the real length of the buffer
-    buffer: Vec<u8>, // Allocated region
-    was_last_chunk: bool,
-}
-
-impl<R> AsyncChunkingReader<R> where R: AsyncRead + Unpin {
-    pub fn new(reader: R) -> Self {
-        Self {
-            reader: Box::new(reader),
-            buffer: vec![],
-            was_last_chunk: false,
-            cursor: 0,
-        }
-    }
+pub async fn decrypt_async(input: impl AsyncRead + Unpin, handle: &Handle, output: impl tokio::io::AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_identity(input, &Identity::Handle(handle.clone()), output).await
+}
 
-    pub fn was_last_chunk(&self) -> bool {
-        self.was_last_chunk
-    }
+pub async fn decrypt_async_with_identity(input: impl AsyncRead + Unpin, identity: &Identity, output: impl tokio::io::AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_identities(input, std::slice::from_ref(identity), output).await
 }
 
-impl<R> AsyncRead for AsyncChunkingReader<R> where R: AsyncRead + Unpin {
-    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
-        let this = self.get_mut();
+pub async fn decrypt_async_with_identities(input: impl AsyncRead + Unpin, identities: &[Identity], output: impl tokio::io::AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    decrypt_async_with_options(input, &DecryptOptions::new().identities(identities.iter().cloned()), output).await
+}
 
-        if this.cursor > output.remaining() {
-            let effective_length = output.remaining();
-            output.put_slice(this.buffer.drain(0..effective_length).as_slice());
-            this.cursor -= effective_length;
+pub async fn decrypt_async_with_options(input: impl AsyncRead + Unpin, options: &DecryptOptions, mut output: impl tokio::io::AsyncWrite + Unpin) -> Result<(), GaiaError> {
+    let mut reader = AsyncDecryptingReader::with_options(Box::new(input), options);
+    copy(&mut reader, &mut output).await
+}
 
-            this.was_last_chunk = false;
+/// Authenticates every chunk of `input` without writing the plaintext anywhere, like [`verify`](crate::verify).
+pub async fn verify_async(input: impl AsyncRead + Unpin, handle: &Handle) -> Result<(), GaiaError> {
+    verify_async_with_options(input, &DecryptOptions::new().identity(Identity::Handle(handle.clone()))).await
+}
 
-            return Poll::Ready(Ok(()));
-        }
+pub async fn verify_async_with_options(input: impl AsyncRead + Unpin, options: &DecryptOptions) -> Result<(), GaiaError> {
+    decrypt_async_with_options(input, options, tokio::io::sink()).await
+}
 
-        let mut pinned_reader = std::pin::pin!(this.reader.as_mut());
+/// Copies all of `reader` into `output` and flushes it, telling the errors of the two apart.
+async fn copy(reader: &mut (impl AsyncRead + Unpin), output: &mut (impl AsyncWrite + Unpin)) -> Result<(), GaiaError> {
+    let mut buffer = vec![0u8; BUF_SIZE];
+    loop {
+        let read = reader.read(&mut buffer).await.map_err(GaiaError::from_input_error)?;
+        if read == 0 {
+            return output.flush().await.map_err(GaiaError::WritingOutput);
+        }
+        output.write_all(&buffer[..read]).await.map_err(GaiaError::WritingOutput)?;
+    }
+}
 
-        let target_size = output.remaining() + 1;
-        this.buffer.resize(target_size, 0);
+/// Marks the readers and writers of tokio, see [`PollSource`].
+pub(crate) enum Tokio {}
 
-        let mut read_buf = ReadBuf::new(&mut this.buffer);
-        read_buf.advance(this.cursor);
+impl<R> PollSource<Tokio> for R where R: AsyncRead + ?Sized {
+    fn poll_read_into(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut read_buf = ReadBuf::new(buffer);
+        ready!(self.poll_read(cx, &mut read_buf))?;
+        Poll::Ready(Ok(read_buf.filled().len()))
+    }
+}
 
-        loop {
-            let previous_position = read_buf.filled().len();
-            ready!(pinned_reader.as_mut().poll_read(cx, &mut read_buf)
-                .map_ok(|_| this.cursor = read_buf.filled().len()))?;
+impl<R> SeekSource<Tokio> for R where R: AsyncSeek + ?Sized {
+    fn poll_seek_to(mut self: Pin<&mut Self>, cx: &mut Context<'_>, position: SeekFrom, started: &mut bool) -> Poll<io::Result<u64>> {
+        if !*started {
+            self.as_mut().start_seek(position)?;
+            *started = true;
+        }
+        let result = ready!(self.poll_complete(cx));
+        *started = false;
+        Poll::Ready(result)
+    }
+}
 
-            if read_buf.remaining() == 0 {
-                break;
+macro_rules! tokio_reader_impl {
+    ($name: ident) => {
+        impl<R> AsyncRead for $name<R> where R: AsyncRead + Unpin {
+            fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+                let read = ready!(self.get_mut().poll_read_output::<Tokio>(cx, output.initialize_unfilled()))?;
+                output.advance(read);
+                Poll::Ready(Ok(()))
             }
+        }
+    };
+}
+
+tokio_reader_impl!(AsyncEncryptingReader);
+tokio_reader_impl!(AsyncDecryptingReader);
 
-            if read_buf.filled().len() == previous_position {
-                this.was_last_chunk = true;
+impl AsyncDecryptingReader<Spool<tokio::fs::File>> {
+    /// Reads and authenticates all of `input` before decrypting any of it, like
+    /// [`DecryptingReader::verify_first`](crate::DecryptingReader::verify_first).
+    pub async fn verify_first(input: impl AsyncRead + Unpin, options: &DecryptOptions, memory_limit: usize) -> io::Result<Self> {
+        let mut spool = Spool::new(memory_limit);
+        let mut verifier = AsyncDecryptingReader::with_options(Tee { reader: input, copy: vec![] }, options);
+        let mut buffer = vec![0u8; BUF_SIZE];
+        loop {
+            let read = verifier.read(&mut buffer).await?;
+            // What was read is moved to the spool between reads, which may write to its file
+            let copy = &mut verifier.get_mut().copy;
+            spool.write_all(copy).await?;
+            copy.clear();
+            if read == 0 {
                 break;
             }
         }
+        spool.rewind().await?;
+        Ok(Self::with_options(spool, options))
+    }
+}
 
-        let effective_length = output.remaining().min(this.cursor);
-        output.put_slice(this.buffer.drain(0..effective_length).as_slice());
-        this.cursor -= effective_length;
-        Poll::Ready(Ok(()))
+impl<R> AsyncDecryptingReader<R> where R: AsyncRead + AsyncSeek + Unpin {
+    /// Like [`verify_first`](AsyncDecryptingReader::verify_first), but reads all of `input` to
+    /// authenticate it and then seeks back to where it started, instead of keeping the ciphertext.
+    pub async fn verify_seekable(mut input: R, options: &DecryptOptions) -> io::Result<Self> {
+        let start = input.stream_position().await?;
+        tokio::io::copy(&mut AsyncDecryptingReader::with_options(&mut input, options), &mut tokio::io::sink()).await?;
+        input.seek(SeekFrom::Start(start)).await?;
+        Ok(Self::with_options(input, options))
     }
 }
 
-macro_rules! async_crypt_reader_impl {
-    ($name: ident, $transform: ident, $next_in_place: ident, $last_in_place: ident, $buf_size_calc: expr) => {
-        pub struct $name<R> where R: AsyncRead + Unpin {
-            chunk_reader: Box<AsyncChunkingReader<R>>,
-            transform: Option<$transform<Cipher, Stream>>,
-            input_buffer: Vec<u8>,
-            completed_buffer: Vec<u8>,
-        }
+/// Seeks to a position in the plaintext by decrypting only the chunk it is in.
+///
+/// Seeking to or past the end of the plaintext decrypts the last chunk all the same, so that a
+/// truncated stream fails to seek instead of seeming to end early.
+impl<R> AsyncSeek for AsyncDecryptingReader<R> where R: AsyncRead + AsyncSeek + Unpin {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.get_mut().start_seek(position)
+    }
 
-        impl<R> $name<R> where R: AsyncRead + Unpin {
-            pub fn new(reader: R, (key, nonce): &Handle) -> Self {
-                let transform = $transform::<Cipher, Stream>::new(key, nonce);
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        self.get_mut().poll_seek::<Tokio>(cx)
+    }
+}
 
-                Self { chunk_reader: Box::new(AsyncChunkingReader::new(reader)), transform: Some(transform),
-                    input_buffer: vec![0; $buf_size_calc], completed_buffer: vec![],
-                }
-            }
+macro_rules! async_crypt_writer_impl {
+    ($name: ident, $setup: ty, $header: ident, $in_place: ident) => {
+        /// The output is only complete once the writer has been shut down, as the last chunk is not
+        /// known until then. Dropping the writer without shutting it down leaves the output truncated.
+        pub struct $name<W> where W: AsyncWrite + Unpin {
+            writer: W,
+            setup: $setup, // Whatever the header hook needs to set up the cipher
+            cipher: Option<ChunkCipher>, // Set up once the header has been handled
+            associated_data: Vec<u8>,
+            position: u32,
+            finished: bool,
+            chunk_length: usize, // Length of a single chunk of input, including the tag when decrypting
+            input_buffer: ChunkBuffer, // Input that has not been transformed yet
+            chunk_buffer: ChunkBuffer,
+            encoded: Vec<u8>, // What the encoder made of the latest input, before it is buffered
+            output_buffer: ChunkBuffer, // Output that the underlying writer has not accepted yet
+            encoder: Option<PlaintextEncoder>, // Compresses and pads the input when encrypting
+            tail: Option<PaddingTail>, // The padding that is left to add once the encoder has been finished
+            decoder: Option<PlaintextDecoder>, // Decompresses or unpads the output when decrypting
         }
 
-        impl<R> AsyncRead for $name<R> where R: AsyncRead + Unpin {
-            fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, output: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
-                let this = self.get_mut();
-
-                let to_read = output.remaining().min(this.completed_buffer.len());
+        impl<W> $name<W> where W: AsyncWrite + Unpin {
+            fn with_parameters(writer: W, setup: $setup, chunk_length: usize) -> Self {
+                Self { writer, setup, cipher: None, associated_data: vec![], position: 0, finished: false,
+                    chunk_length, input_buffer: ChunkBuffer::new(), chunk_buffer: ChunkBuffer::new(), encoded: vec![],
+                    output_buffer: ChunkBuffer::new(), encoder: None, tail: None, decoder: None }
+            }
 
-                if to_read != 0 {
-                    output.put_slice(this.completed_buffer.drain(0..to_read).as_ref());
-                    return Poll::Ready(Ok(()));
+            /// Buffers `input`, encoding it first if there is an encoder.
+            fn buffer_input(&mut self, input: &[u8]) -> io::Result<()> {
+                match self.encoder {
+                    Some(ref mut encoder) => {
+                        self.encoded.clear();
+                        encoder.encode(input, &mut self.encoded)?;
+                        self.input_buffer.append(&self.encoded);
+                    }
+                    None => self.input_buffer.append(input),
                 }
+                Ok(())
+            }
 
-                let mut chunk_reader = std::pin::pin!(this.chunk_reader.as_mut());
+            /// Consumes the writer, returning the underlying writer.
+            pub fn into_inner(self) -> W {
+                self.writer
+            }
 
-                let filled_len = {
-                    let mut read_buf = ReadBuf::new(&mut this.input_buffer);
-                    ready!(chunk_reader.as_mut().poll_read(cx, &mut read_buf))?;
-                    read_buf.filled().len()
+            /// Transforms every buffered chunk that more input is known to follow, and the rest as
+            /// the last chunk when finishing.
+            fn transform_chunks(&mut self, finishing: bool) -> io::Result<()> {
+                let cipher = match self.cipher {
+                    Some(ref cipher) => cipher,
+                    None => match self.$header(finishing)? {
+                        Some(cipher) => self.cipher.insert(cipher),
+                        None => return Ok(()), // The header is not complete yet
+                    }
                 };
 
-                if filled_len == 0 { // end-of-file
-                    return Poll::Ready(Ok(()));
-                }
-
-                this.input_buffer.truncate(filled_len);
+                while self.input_buffer.len() > self.chunk_length || finishing {
+                    let length = self.chunk_length.min(self.input_buffer.len());
+                    let last_chunk = finishing && length == self.input_buffer.len();
+
+                    self.chunk_buffer.clear();
+                    self.chunk_buffer.reserve(self.chunk_length + TAG_LENGTH);
+                    self.chunk_buffer.push_slice(&self.input_buffer.unread()[..length]);
+                    self.input_buffer.consume(length);
+                    cipher.$in_place(self.position, last_chunk, &self.associated_data, &mut self.chunk_buffer)
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                    match self.decoder {
+                        Some(ref mut decoder) => decoder.decode_into(self.chunk_buffer.unread(), &mut self.output_buffer)?,
+                        None => self.output_buffer.append(self.chunk_buffer.unread()),
+                    }
+                    self.position += 1;
 
-                if !chunk_reader.was_last_chunk() {
-                    if let Some(ref mut transform) = this.transform.as_mut() {
-                        transform.$next_in_place(&[], &mut this.input_buffer)
-                            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
-                        this.completed_buffer.extend_from_slice(&this.input_buffer);
+                    if last_chunk {
+                        break;
                     }
-                } else {
-                    if let Some(transform) = this.transform.take() {
-                        transform.$last_in_place(&[], &mut this.input_buffer)
-                            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
-                        this.completed_buffer.extend_from_slice(&this.input_buffer);
+                }
+                Ok(())
+            }
+
+            fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                while !self.output_buffer.is_empty() {
+                    let written = ready!(Pin::new(&mut self.writer).poll_write(cx, self.output_buffer.unread()))?;
+                    if written == 0 {
+                        return Poll::Ready(Err(ErrorKind::WriteZero.into()));
                     }
+                    self.output_buffer.consume(written);
                 }
+                Poll::Ready(Ok(()))
+            }
+        }
 
-                this.input_buffer = vec![0u8; $buf_size_calc];
+        impl<W> AsyncWrite for $name<W> where W: AsyncWrite + Unpin {
+            fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, input: &[u8]) -> Poll<io::Result<usize>> {
+                let this = self.get_mut();
+                if this.finished {
+                    return Poll::Ready(Err(io::Error::other("write after the writer was shut down")));
+                }
 
-                let effective_length = output.remaining().min(this.completed_buffer.len());
-                output.put_slice(&this.completed_buffer[0..effective_length]);
-                this.completed_buffer.drain(0..effective_length);
+                ready!(this.poll_write_output(cx))?;
+                this.buffer_input(input)?;
+                this.transform_chunks(false)?;
+                Poll::Ready(Ok(input.len()))
+            }
 
+            /// Flushes the underlying writer. Input that is not yet known to be followed by more input
+            /// stays buffered, as it may be the last chunk.
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let this = self.get_mut();
+                ready!(this.poll_write_output(cx))?;
+                Pin::new(&mut this.writer).poll_flush(cx)
+            }
 
-                Poll::Ready(Ok(()))
+            /// Transforms the last chunk, writes out everything and shuts down the underlying writer.
+            fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let this = self.get_mut();
+                if !this.finished {
+                    if let Some(encoder) = this.encoder.take() {
+                        this.encoded.clear();
+                        this.tail = Some(encoder.finish(&mut this.encoded)?);
+                        this.input_buffer.append(&this.encoded);
+                    }
+                    // The padding is added a chunk at a time, as there can be a lot of it
+                    while this.tail.as_ref().is_some_and(|tail| !tail.is_empty()) {
+                        ready!(this.poll_write_output(cx))?;
+                        let tail = this.tail.as_mut().expect("the tail was just checked");
+                        this.encoded.clear();
+                        tail.fill(&mut this.encoded, this.chunk_length);
+                        this.input_buffer.append(&this.encoded);
+                        this.transform_chunks(false)?;
+                    }
+                    this.transform_chunks(true)?;
+                    if let Some(ref decoder) = this.decoder {
+                        decoder.finish()?;
+                    }
+                    this.finished = true;
+                }
+
+                ready!(this.poll_write_output(cx))?;
+                Pin::new(&mut this.writer).poll_shutdown(cx)
             }
         }
     };
 }
 
-async_crypt_reader_impl!(AsyncEncryptingReader, Encryptor, encrypt_next_in_place, encrypt_last_in_place, BUF_SIZE);
-async_crypt_reader_impl!(AsyncDecryptingReader, Decryptor, decrypt_next_in_place, decrypt_last_in_place, BUF_SIZE + StreamTagLength::to_usize());
+async_crypt_writer_impl!(AsyncEncryptingWriter, (Handle, EncryptOptions), write_header, encrypt_in_place);
+async_crypt_writer_impl!(AsyncDecryptingWriter, DecryptOptions, read_header, decrypt_in_place);
+
+impl<W> AsyncEncryptingWriter<W> where W: AsyncWrite + Unpin {
+    pub fn new(writer: W, handle: &Handle) -> Self {
+        Self::with_options(writer, handle, &EncryptOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the handle was not generated for the algorithm in the options.
+    pub fn with_options(writer: W, handle: &Handle, options: &EncryptOptions) -> Self {
+        assert_eq!(handle.nonce().len(), options.algorithm.nonce_length(), "the handle does not fit {}", options.algorithm);
+        Self { encoder: PlaintextEncoder::new(options),
+            ..Self::with_parameters(writer, (handle.clone(), options.clone()), options.chunk_size) }
+    }
 
-impl<R> AsyncEncryptingReader<R> where R: AsyncRead + Unpin {
-    pub fn new_with_os_rng(reader: R) -> (Self, Handle) {
+    /// Encrypts with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(writer: W, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(writer, handle, &EncryptOptions::default().associated_data(associated_data))
+    }
+
+    pub fn new_with_os_rng(writer: W) -> (Self, Handle) {
         let handle = generate_handle(&mut OsRng);
-        (Self::new(reader, &handle), handle)
+        (Self::new(writer, &handle), handle)
+    }
+
+    fn write_header(&mut self, _finishing: bool) -> io::Result<Option<ChunkCipher>> {
+        let (handle, options) = &self.setup;
+        let preamble = write_preamble(handle, options)?;
+        self.output_buffer.append(&preamble);
+        self.associated_data = [preamble.as_slice(), &options.associated_data].concat();
+        Ok(Some(ChunkCipher::new(options.algorithm, handle).expect("the handle was checked to fit the algorithm")))
+    }
+}
+
+impl<W> AsyncDecryptingWriter<W> where W: AsyncWrite + Unpin {
+    pub fn new(writer: W, handle: &Handle) -> Self {
+        Self::with_identity(writer, Identity::Handle(handle.clone()))
     }
-}
\ No newline at end of file
+
+    /// Decrypts with any identity the stream was encrypted for, such as a passphrase.
+    pub fn with_identity(writer: W, identity: Identity) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identity(identity))
+    }
+
+    /// Decrypts with whichever of the identities the stream was encrypted for, trying them in order.
+    pub fn with_identities(writer: W, identities: impl IntoIterator<Item = Identity>) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identities(identities))
+    }
+
+    /// Decrypts a stream that was encrypted with associated data, see [`EncryptOptions::associated_data`].
+    pub fn with_aad(writer: W, handle: &Handle, associated_data: &[u8]) -> Self {
+        Self::with_options(writer, &DecryptOptions::new().identity(Identity::Handle(handle.clone())).associated_data(associated_data))
+    }
+
+    pub fn with_options(writer: W, options: &DecryptOptions) -> Self {
+        // The chunk length is only known once the header has been read
+        Self::with_parameters(writer, options.clone(), 0)
+    }
+
+    fn read_header(&mut self, finishing: bool) -> io::Result<Option<ChunkCipher>> {
+        let invalid_data = |e: HeaderError| io::Error::new(ErrorKind::InvalidData, e);
+
+        let Some(length) = preamble_length(self.input_buffer.unread()).map_err(invalid_data)? else {
+            return match finishing {
+                true => Err(invalid_data(HeaderError::Truncated)),
+                false => Ok(None),
+            };
+        };
+
+        let Opened { cipher, chunk_length, associated_data, decoder, .. } = self.setup.open(&self.input_buffer.unread()[..length])?;
+        self.input_buffer.consume(length);
+        self.chunk_length = chunk_length;
+        self.associated_data = associated_data;
+        self.decoder = decoder;
+        Ok(Some(cipher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, SeekFrom};
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use crate::{decrypt_async, encrypt_async, encrypt_async_with_options, generate_handle, AsyncDecryptingReader, AsyncDecryptingWriter, AsyncEncryptingReader, AsyncEncryptingWriter, DecryptOptions, EncryptOptions, Identity, Padding};
+    use crate::algorithm::TAG_LENGTH;
+    use crate::header::inspect;
+
+    const CHUNK_SIZE: usize = 64;
+
+    #[tokio::test]
+    async fn empty_input_round_trips() {
+        let mut ciphertext = vec![];
+        let handle = encrypt_async([].as_slice(), &mut ciphertext).await.unwrap();
+        assert_eq!(inspect(ciphertext.as_slice()).unwrap().final_chunk_length, Some(TAG_LENGTH as u64));
+
+        let mut plaintext = vec![];
+        decrypt_async(ciphertext.as_slice(), &handle, &mut plaintext).await.unwrap();
+        assert!(plaintext.is_empty());
+    }
+
+    #[tokio::test]
+    async fn readers_round_trip_empty_input() {
+        let (mut reader, handle) = AsyncEncryptingReader::new_with_os_rng([].as_slice());
+        let mut ciphertext = vec![];
+        reader.read_to_end(&mut ciphertext).await.unwrap();
+
+        let mut plaintext = vec![];
+        AsyncDecryptingReader::new(ciphertext.as_slice(), &handle).read_to_end(&mut plaintext).await.unwrap();
+        assert!(plaintext.is_empty());
+        assert!(AsyncDecryptingReader::new([].as_slice(), &handle).read_to_end(&mut plaintext).await.is_err());
+        let preamble_length = ciphertext.len() - TAG_LENGTH;
+        assert!(AsyncDecryptingReader::new(&ciphertext[..preamble_length], &handle).read_to_end(&mut plaintext).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn seek_lands_in_the_right_chunk() {
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let mut ciphertext = vec![];
+        let handle = encrypt_async_with_options(plaintext.as_slice(), &mut ciphertext, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).await.unwrap();
+
+        // The stream does not have to start at the beginning of its reader
+        let mut input = Cursor::new([b"prefix".as_slice(), &ciphertext].concat());
+        input.set_position(6);
+        let mut reader = AsyncDecryptingReader::new(input, &handle);
+        for position in [CHUNK_SIZE + 1, 0, 3 * CHUNK_SIZE + 9, 2 * CHUNK_SIZE] {
+            assert_eq!(reader.seek(SeekFrom::Start(position as u64)).await.unwrap(), position as u64);
+            let mut rest = vec![];
+            reader.read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, &plaintext[position..], "from {position}");
+        }
+
+        let truncated = &ciphertext[..ciphertext.len() - 10 - TAG_LENGTH];
+        assert!(AsyncDecryptingReader::new(Cursor::new(truncated), &handle).seek(SeekFrom::End(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn verify_first_never_releases_plaintext() {
+        let plaintext = vec![7; 10 * CHUNK_SIZE];
+        let mut ciphertext = vec![];
+        let handle = encrypt_async_with_options(plaintext.as_slice(), &mut ciphertext, &EncryptOptions::new().chunk_size(CHUNK_SIZE)).await.unwrap();
+        let options = DecryptOptions::new().identity(Identity::Handle(handle));
+
+        for memory_limit in [usize::MAX, CHUNK_SIZE] {
+            let mut decrypted = vec![];
+            AsyncDecryptingReader::verify_first(ciphertext.as_slice(), &options, memory_limit).await.unwrap().read_to_end(&mut decrypted).await.unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut tampered = ciphertext.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(AsyncDecryptingReader::verify_first(tampered.as_slice(), &options, memory_limit).await.is_err());
+        }
+        assert!(AsyncDecryptingReader::verify_seekable(Cursor::new(&ciphertext[..ciphertext.len() - 1]), &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writers_match_the_readers() {
+        let plaintext: Vec<u8> = (0..10 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        for padding in [Padding::None, Padding::Padme] {
+            let options = EncryptOptions::new().chunk_size(CHUNK_SIZE).padding(padding);
+            let handle = generate_handle(&mut aead::OsRng);
+            let mut expected = vec![];
+            AsyncEncryptingReader::with_options(plaintext.as_slice(), &handle, &options).read_to_end(&mut expected).await.unwrap();
+
+            // Pieces of uneven lengths, some shorter and some longer than a chunk
+            let mut writer = AsyncEncryptingWriter::with_options(vec![], &handle, &options);
+            for piece in plaintext.chunks(3 * CHUNK_SIZE / 2 + 1) {
+                writer.write_all(piece).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            let ciphertext = writer.into_inner();
+            assert_eq!(ciphertext, expected, "{padding:?}");
+
+            let mut writer = AsyncDecryptingWriter::new(vec![], &handle);
+            for piece in ciphertext.chunks(CHUNK_SIZE / 2 + 3) {
+                writer.write_all(piece).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            assert_eq!(writer.into_inner(), plaintext, "{padding:?}");
+
+            let mut writer = AsyncDecryptingWriter::new(vec![], &handle);
+            writer.write_all(&ciphertext[..ciphertext.len() - 1]).await.unwrap();
+            assert!(writer.shutdown().await.is_err(), "{padding:?}");
+        }
+    }
+}
@@ -6,8 +6,13 @@ use gaia::keystore;
 pub enum CliError {
     OpeningInput(String, std::io::Error),
     OpeningOutput(String, std::io::Error),
+    WritingOutput(String, std::io::Error),
     WritingSecret(String, std::io::Error),
-    InvalidSecret(keystore::ConversionError)
+    InvalidSecret(keystore::ConversionError),
+    ReadingPassphrase(std::io::Error),
+    ReadingIdentity(String, std::io::Error),
+    PassphraseMismatch,
+    MissingName,
 }
 
 impl<'a> Debug for CliError {
@@ -15,8 +20,13 @@ impl<'a> Debug for CliError {
         match self {
             Self::OpeningInput(path, _) => write!(f, "failed to open the {path} for the input"),
             Self::OpeningOutput(path, _) => write!(f, "failed to open the {path} for the output"),
+            Self::WritingOutput(path, _) => write!(f, "failed to write the output to the {path}"),
             Self::WritingSecret(path, _) => write!(f, "failed to open the {path} for the secret"),
             Self::InvalidSecret(_) => write!(f, "failed to decrypt the invalid key"),
+            Self::ReadingPassphrase(_) => write!(f, "failed to read the passphrase from the terminal"),
+            Self::ReadingIdentity(path, _) => write!(f, "failed to open the {path} for the identity"),
+            Self::PassphraseMismatch => write!(f, "the passphrases did not match"),
+            Self::MissingName => write!(f, "failed to restore the name of the file"),
         }
     }
 }
@@ -25,9 +35,14 @@ impl<'a> Display for CliError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::OpeningInput(path, _) => write!(f, "could not read from the {path}"),
-            Self::OpeningOutput(path, _) => write!(f, "could not write to the {path}"),
+            Self::OpeningOutput(path, _) => write!(f, "could not open the {path}"),
+            Self::WritingOutput(path, _) => write!(f, "could not write to the {path}"),
             Self::WritingSecret(path, _) => write!(f, "could not write the secret to the {path}"),
-            Self::InvalidSecret(_) => write!(f, "the provided secret is invalid")
+            Self::InvalidSecret(_) => write!(f, "the provided secret is invalid"),
+            Self::ReadingPassphrase(_) => write!(f, "could not read the passphrase"),
+            Self::ReadingIdentity(path, _) => write!(f, "could not read the identity from the {path}"),
+            Self::PassphraseMismatch => write!(f, "the passphrases do not match"),
+            Self::MissingName => write!(f, "the file has no stored name that is safe to restore")
         }
     }
 }
@@ -37,9 +52,13 @@ impl Error for CliError {
         match self {
             Self::OpeningInput(.., cause)
             | Self::OpeningOutput(.., cause)
-            | Self::WritingSecret(.., cause) => Some(cause),
+            | Self::WritingOutput(.., cause)
+            | Self::WritingSecret(.., cause)
+            | Self::ReadingIdentity(.., cause)
+            | Self::ReadingPassphrase(cause) => Some(cause),
 
             Self::InvalidSecret(cause) => Some(cause),
+            Self::PassphraseMismatch | Self::MissingName => None,
         }
     }
 }
\ No newline at end of file
@@ -2,14 +2,39 @@ mod error;
 
 use std::borrow::Cow;
 use std::io;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use main_error::MainError;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use gaia::{decrypt_async, encrypt_async, keystore::{from_secret, to_secret}};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use gaia::{encrypt_async_with_options, encrypt_file, open, seal, verify_async_with_options, error::{GaiaError, StreamError}, header::{inspect, Header, HeaderError, Layout, FLAG_KEY_COMMITMENT, FLAG_METADATA, FLAG_PADDED, FLAG_RECIPIENTS, FLAG_ZSTD, HEADER_LENGTH}, Algorithm, AsyncDecryptingReader, Compression, DecryptOptions, EncryptOptions, Identity, Key, Metadata, Padding, PassphraseParams, Recipient, MAX_CHUNK_SIZE};
+use gaia::keystore::{from_identity, from_key, from_public_key, from_sealed, from_secret, to_identity, to_key, to_public_key, to_sealed, to_secret};
+use gaia::x25519_dalek::{PublicKey, StaticSecret};
 use crate::error::CliError;
 
+/// How much of the input `decrypt --verify-first` keeps in memory before moving it to a temporary file.
+const VERIFY_MEMORY_LIMIT: usize = 64 << 20;
+
+/// Exit codes of `verify` for an input that is not intact. Other errors exit with 1, and wrong usage with 2.
+const EXIT_WRONG_KEY: i32 = 3;
+const EXIT_CORRUPTED: i32 = 4;
+const EXIT_TRUNCATED: i32 = 5;
+
+/// The exit code of `verify` for an input that failed to verify with `error`, or `None` if the
+/// input could not be read at all. `committed` tells whether the input commits to its key.
+fn verify_exit_code(error: &GaiaError, committed: bool) -> Option<i32> {
+    // A first chunk that does not decrypt is put down to the key, as the key being wrong looks just like it,
+    // unless the key has already been checked against the commitment, so that the chunk must have been tampered with
+    Some(match error {
+        GaiaError::InvalidHeader(HeaderError::HandleMismatch(_) | HeaderError::NoMatchingIdentity | HeaderError::CommitmentMismatch) => EXIT_WRONG_KEY,
+        GaiaError::InvalidHeader(HeaderError::InvalidMetadata)
+        | GaiaError::Decrypting(StreamError::Unauthenticated { chunk: 0, .. }) if !committed => EXIT_WRONG_KEY,
+        GaiaError::InvalidHeader(HeaderError::Truncated) | GaiaError::Decrypting(StreamError::Truncated { .. }) => EXIT_TRUNCATED,
+        GaiaError::InvalidHeader(_) | GaiaError::Decrypting(_) => EXIT_CORRUPTED,
+        _ => return None,
+    })
+}
+
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Encrypt and decrypt files.", long_about = "Gaia is a command-line application for encrypting and decrypting files.")]
+#[command(name = "gaia", author, version, about = "Encrypt and decrypt files.", long_about = "Gaia is a command-line application for encrypting and decrypting files.")]
 struct Args {
     #[command(subcommand)]
     command: Commands
@@ -23,17 +48,102 @@ enum Commands {
         file_path: Cow<'static, str>,
         #[arg(name = "output path", long = "output", short = 'o', default_value = "e.out", help = "Output for the encrypted file, like /home/alice/SECRET_LETTER.enc")]
         output: Cow<'static, str>,
-        #[arg(name = "secret path", long = "secret", short = 's', default_value = "-", help = "Output for the secret decryption key, like /home/alice/LETTER.key")]
-        secret_path: Cow<'static, str>
+        #[arg(name = "secret path", long = "secret", short = 's', help = "Output for the secret decryption key, like /home/alice/LETTER.key [default: -, or none with --passphrase or --recipient]")]
+        secret_path: Option<Cow<'static, str>>,
+        #[arg(name = "chunk size", long = "chunk-size", value_parser = clap::value_parser!(u32).range(1..=MAX_CHUNK_SIZE as i64), help = "Number of bytes to encrypt at a time, like 1048576 [default: 16384]")]
+        chunk_size: Option<u32>,
+        #[arg(name = "algorithm", long = "algorithm", short = 'a', default_value_t = Algorithm::default(), help = "The cipher to encrypt with: aes-256-gcm-siv, chacha20-poly1305 or xchacha20-poly1305")]
+        algorithm: Algorithm,
+        #[arg(name = "passphrase", long = "passphrase", short = 'p', conflicts_with = "recipient", help = "Prompt for a passphrase that can decrypt the file instead of the secret key. Cannot be given with --recipient.")]
+        passphrase: bool,
+        #[arg(name = "recipient", long = "recipient", short = 'r', help = "Public key or secret key of someone who can decrypt the file instead of the secret key, like gaia-public-xOV6...WNM=. Can be given many times.")]
+        recipients: Vec<Cow<'static, str>>,
+        #[arg(name = "associated data", long = "aad", help = "Context to bind the file to, which must be given again to decrypt it, like report.pdf or @/home/alice/context.txt to read it from a file")]
+        aad: Option<Cow<'static, str>>,
+        #[arg(name = "compression level", long = "compress", num_args = 0..=1, require_equals = true, default_missing_value = "3", value_parser = clap::value_parser!(i32).range(1..=22), help = "Compress the file with zstd before encrypting it, optionally at a level from 1 to 22 like --compress=19 [default level: 3]")]
+        compression_level: Option<i32>,
+        #[arg(name = "no compression", long = "no-compress", overrides_with = "compression level", help = "Do not compress the file, such as when it is already compressed")]
+        no_compression: bool,
+        #[arg(name = "padding", long = "pad", num_args = 0..=1, require_equals = true, default_missing_value = "padme", help = "Pad the file so that its encrypted size only gives away roughly how large it is, with padme or power-of-two like --pad=power-of-two [default: padme]")]
+        padding: Option<Padding>,
+        #[arg(name = "metadata", long = "metadata", help = "Store the name, modification time and permissions of the file in it, so that decrypt can restore them")]
+        metadata: bool,
+        #[arg(name = "mime type", long = "mime-type", help = "Store the MIME type of the file in it, like text/plain")]
+        mime_type: Option<Cow<'static, str>>,
+        #[arg(name = "key commitment", long = "key-commitment", help = "Commit the file to its key, so that it cannot be crafted to decrypt differently under other keys")]
+        key_commitment: bool,
+        #[arg(name = "memory", long = "argon2-memory", requires = "passphrase", value_parser = clap::value_parser!(u32).range(8..=PassphraseParams::MAX_MEMORY_KIB as i64), default_value_t = PassphraseParams::default().memory_kib, help = "KiB of memory for deriving a key from the passphrase")]
+        memory_kib: u32,
+        #[arg(name = "iterations", long = "argon2-iterations", requires = "passphrase", value_parser = clap::value_parser!(u32).range(1..=PassphraseParams::MAX_ITERATIONS as i64), default_value_t = PassphraseParams::default().iterations, help = "Passes over the memory for deriving a key from the passphrase")]
+        iterations: u32,
+        #[arg(name = "parallelism", long = "argon2-parallelism", requires = "passphrase", value_parser = clap::value_parser!(u32).range(1..=PassphraseParams::MAX_PARALLELISM as i64), default_value_t = PassphraseParams::default().parallelism, help = "Lanes for deriving a key from the passphrase")]
+        parallelism: u32
     },
-    #[command(about = "Decrypts a file.", long_about = "Decrypts a file using the given key and writes it to the given output file.")]
+    #[command(about = "Decrypts a file.", long_about = "Decrypts a file using the given keys, passphrase or identities and writes it to the given output file.")]
     Decrypt {
-        #[arg(name = "secret key", help = "The secret key for decryption, like CJLote8FEmo...vBSAD.")]
-        secret_key: Cow<'static, str>,
+        #[arg(name = "secret key", help = "The secret key for decryption, like CJLote8FEmo...vBSAD. Can be left out with --passphrase or --identity.")]
+        secret_key: Option<Cow<'static, str>>,
         #[arg(name = "file path", help = "The file to decrypt, like /home/bob/Downloads/SECRET_LETTER.enc.")]
-        file_path: Cow<'static, str>,
+        file_path: Option<Cow<'static, str>>,
         #[arg(name = "output path", long = "output", short = 'o', default_value = "d.out", help = "Output for the decrypted file, like /home/bob/MYSTERY_LETTER.txt")]
+        output: Cow<'static, str>,
+        #[arg(name = "passphrase", long = "passphrase", short = 'p', help = "Prompt for the passphrase instead of taking a secret key")]
+        passphrase: bool,
+        #[arg(name = "identity path", long = "identity", short = 'i', help = "Identity file made with keygen to decrypt with instead of a secret key, like /home/bob/.gaia/identity. Can be given many times.")]
+        identity_paths: Vec<Cow<'static, str>>,
+        #[arg(name = "associated data", long = "aad", help = "Context the file was bound to when encrypting it, like report.pdf or @/home/alice/context.txt to read it from a file")]
+        aad: Option<Cow<'static, str>>,
+        #[arg(name = "restore name", long = "restore-name", conflicts_with = "output path", help = "Write to the name stored in the file with --metadata, in the current directory, instead of the output. Fails if the name is missing, is a path or already exists.")]
+        restore_name: bool,
+        #[arg(name = "restore attributes", long = "restore-attributes", help = "Set the modification time and permissions of the output to the ones stored in the file with --metadata")]
+        restore_attributes: bool,
+        #[arg(name = "verify first", long = "verify-first", help = "Authenticate the whole file before writing any of it, keeping a copy of the input in memory or a temporary file unless it is a regular file")]
+        verify_first: bool
+    },
+    #[command(about = "Verifies a file.", long_about = "Checks that every chunk of an encrypted file decrypts with the given keys, passphrase or identities without writing the plaintext anywhere. \
+Exits with 0 if the file is intact, 3 if the key is wrong, 4 if the file is corrupted and 5 if it has been cut short. \
+Unless the file was encrypted with --key-commitment, a corrupted first chunk cannot be told apart from a wrong key and exits with 3.")]
+    Verify {
+        #[arg(name = "secret key", help = "The secret key for decryption, like CJLote8FEmo...vBSAD. Can be left out with --passphrase or --identity.")]
+        secret_key: Option<Cow<'static, str>>,
+        #[arg(name = "file path", help = "The file to verify, like /home/bob/Archive/SECRET_LETTER.enc.")]
+        file_path: Option<Cow<'static, str>>,
+        #[arg(name = "passphrase", long = "passphrase", short = 'p', help = "Prompt for the passphrase instead of taking a secret key")]
+        passphrase: bool,
+        #[arg(name = "identity path", long = "identity", short = 'i', help = "Identity file made with keygen to decrypt with instead of a secret key, like /home/bob/.gaia/identity. Can be given many times.")]
+        identity_paths: Vec<Cow<'static, str>>,
+        #[arg(name = "associated data", long = "aad", help = "Context the file was bound to when encrypting it, like report.pdf or @/home/alice/context.txt to read it from a file")]
+        aad: Option<Cow<'static, str>>
+    },
+    #[command(about = "Inspects a file.", long_about = "Prints what can be told about an encrypted file without its key: its format version, algorithm, flags and chunk size, and how it is split into chunks.")]
+    Inspect {
+        #[arg(name = "file path", help = "The file to inspect, like /home/bob/Downloads/SECRET_LETTER.enc.")]
+        file_path: Cow<'static, str>,
+        #[arg(name = "json", long = "json", help = "Print the report as JSON")]
+        json: bool
+    },
+    #[command(about = "Generates an identity.", long_about = "Generates an identity for decrypting files and writes it to the given output file. Files are encrypted for it with its public key, which is printed.")]
+    Keygen {
+        #[arg(name = "output path", long = "output", short = 'o', default_value = "-", help = "Output for the identity, like /home/bob/.gaia/identity")]
         output: Cow<'static, str>
+    },
+    #[command(about = "Seals a short message.", long_about = "Seals a short message with a key in a compact format and prints it as URL-safe BASE64. Without a key, a new one is generated and printed to standard error.")]
+    Seal {
+        #[arg(name = "message", help = "The message to seal, like hunter2. Read from standard input if left out.")]
+        message: Option<Cow<'static, str>>,
+        #[arg(name = "key", long = "key", short = 'k', help = "Key to seal with, like gaia-key-3q2T...Hw= [default: a new key]")]
+        key: Option<Cow<'static, str>>,
+        #[arg(name = "associated data", long = "aad", help = "Context to bind the message to, which must be given again to open it, like db-password or @/home/alice/context.txt to read it from a file")]
+        aad: Option<Cow<'static, str>>
+    },
+    #[command(about = "Opens a sealed message.", long_about = "Opens a message sealed with the given key and prints it.")]
+    Open {
+        #[arg(name = "key", help = "The key the message was sealed with, like gaia-key-3q2T...Hw=")]
+        key: Cow<'static, str>,
+        #[arg(name = "sealed message", help = "The sealed message, like AeE7...kQ==. Read from standard input if left out.")]
+        sealed: Option<Cow<'static, str>>,
+        #[arg(name = "associated data", long = "aad", help = "Context the message was bound to when sealing it, like db-password or @/home/alice/context.txt to read it from a file")]
+        aad: Option<Cow<'static, str>>
     }
 }
 
@@ -70,26 +180,410 @@ async fn main() -> Result<(), MainError> {
         })
     }
 
+    /// Whether `path` is a regular file, or does not exist yet if `may_be_missing` is set.
+    fn is_regular_file(path: &str, may_be_missing: bool) -> bool {
+        match std::fs::metadata(path) {
+            _ if path == "-" || path == "^" => false,
+            Ok(metadata) => metadata.is_file(),
+            Err(e) => may_be_missing && e.kind() == io::ErrorKind::NotFound,
+        }
+    }
+
+    async fn read_identities(path: &str) -> Result<Vec<StaticSecret>, CliError> {
+        let contents = tokio::fs::read_to_string(path).await.map_err(|e| CliError::ReadingIdentity(format!("identity file '{path}'"), e))?;
+        contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| from_identity(line).map_err(CliError::InvalidSecret)).collect()
+    }
+
+    async fn read_associated_data(aad: &str) -> Result<Vec<u8>, CliError> {
+        match aad.strip_prefix('@') {
+            Some(path) => tokio::fs::read(path).await.map_err(|e| CliError::OpeningInput(format!("associated data file '{path}'"), e)),
+            None => Ok(aad.as_bytes().to_vec()),
+        }
+    }
+
+    fn flag_names(flags: u16) -> Vec<&'static str> {
+        [(FLAG_RECIPIENTS, "recipients"), (FLAG_ZSTD, "zstd"), (FLAG_PADDED, "padded"), (FLAG_METADATA, "metadata"), (FLAG_KEY_COMMITMENT, "key-commitment")]
+            .into_iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| name).collect()
+    }
+
+    /// Formats `layout` as a JSON object. The layout is taken apart without `..`, so that a field
+    /// added to it does not compile until it is added here too.
+    fn layout_json(layout: &Layout) -> String {
+        let Layout { header, recipients, preamble_length, full_chunks, final_chunk_length, plaintext_length, trailing_bytes } = layout;
+        let Header { version, algorithm, flags, chunk_size } = header;
+        let flags: Vec<_> = flag_names(*flags).iter().map(|flag| format!("\"{flag}\"")).collect();
+        let fields = [
+            ("format", "\"gaia\"".to_string()),
+            ("version", version.to_string()),
+            ("algorithm", format!("\"{algorithm}\"")),
+            ("flags", format!("[{}]", flags.join(","))),
+            ("recipients", recipients.to_string()),
+            ("chunk_size", chunk_size.to_string()),
+            ("preamble_length", preamble_length.to_string()),
+            ("full_chunks", full_chunks.to_string()),
+            ("final_chunk_length", final_chunk_length.map_or("null".to_string(), |length| length.to_string())),
+            ("plaintext_length", plaintext_length.to_string()),
+            ("trailing_bytes", trailing_bytes.to_string()),
+        ];
+        let fields: Vec<_> = fields.iter().map(|(name, value)| format!("\"{name}\":{value}")).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Reads a short string from `argument`, or from standard input without its trailing newline.
+    async fn read_argument(argument: &Option<Cow<'static, str>>) -> Result<String, CliError> {
+        if let Some(argument) = argument {
+            return Ok(argument.to_string());
+        }
+
+        let mut input = String::new();
+        tokio::io::stdin().read_to_string(&mut input).await.map_err(|e| CliError::OpeningInput(input_name("-"), e))?;
+        let length = input.trim_end_matches(['\r', '\n']).len();
+        input.truncate(length);
+        Ok(input)
+    }
+
+    fn read_passphrase(confirm: bool) -> Result<String, CliError> {
+        let passphrase = rpassword::prompt_password("Passphrase: ").map_err(CliError::ReadingPassphrase)?;
+        if confirm && rpassword::prompt_password("Repeat passphrase: ").map_err(CliError::ReadingPassphrase)? != passphrase {
+            return Err(CliError::PassphraseMismatch);
+        }
+        Ok(passphrase)
+    }
+
+    /// Gathers the keys that `subcommand` decrypts its input with, and the input. The secret key comes
+    /// first, so with a passphrase or identity alone the only positional is the file.
+    async fn read_decrypt_options<'a>(subcommand: &str, secret_key: &'a Option<Cow<'static, str>>, file_path: &'a Option<Cow<'static, str>>,
+                                      passphrase: bool, identity_paths: &[Cow<'static, str>], aad: &Option<Cow<'static, str>>) -> Result<(&'a str, DecryptOptions), CliError> {
+        let mut identities = vec![];
+        let input = match (secret_key, file_path) {
+            (Some(secret_key), Some(input)) => {
+                identities.push(Identity::Handle(from_secret(secret_key.as_ref()).map_err(|e| CliError::InvalidSecret(e))?));
+                input
+            }
+            (Some(input), None) if passphrase || !identity_paths.is_empty() => input,
+            _ => {
+                let mut command = Args::command();
+                command.build();
+                command.find_subcommand_mut(subcommand).unwrap().error(clap::error::ErrorKind::MissingRequiredArgument,
+                    "expected a secret key and a file, or a file with --passphrase or --identity").exit()
+            }
+        };
+
+        for path in identity_paths {
+            identities.extend(read_identities(path).await?.into_iter().map(Identity::X25519));
+        }
+        if passphrase {
+            identities.push(Identity::Passphrase(read_passphrase(false)?));
+        }
+
+        let mut options = DecryptOptions::new().identities(identities);
+        if let Some(aad) = aad {
+            options = options.associated_data(read_associated_data(aad).await?);
+        }
+        Ok((input, options))
+    }
+
+    /// Writes out what `reader` decrypts, to the name stored in it with `restore_name`.
+    async fn write_decrypted<R: AsyncRead + Unpin>(mut reader: AsyncDecryptingReader<R>, output: &str, restore_name: bool, restore_attributes: bool) -> Result<(), MainError> {
+        // The metadata is in the preamble, so it is known before anything is written
+        let metadata = reader.metadata().await.map_err(GaiaError::from_input_error)?.cloned().unwrap_or_default();
+        let (output, mut output_file) = match restore_name {
+            true => {
+                let name = metadata.safe_name().ok_or(CliError::MissingName)?;
+                let file = tokio::fs::OpenOptions::new().create_new(true).write(true).open(name).await
+                    .map_err(|e| CliError::OpeningOutput(output_name(name), e))?;
+                (name, Box::new(file) as Box<dyn AsyncWrite + Unpin>)
+            }
+            false => (output, open_output(output).await.map_err(|e| CliError::OpeningOutput(output_name(output), e))?),
+        };
+
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            let read = reader.read(&mut buffer).await.map_err(GaiaError::from_input_error)?;
+            if read == 0 {
+                break;
+            }
+            output_file.write_all(&buffer[..read]).await.map_err(|e| CliError::WritingOutput(output_name(output), e))?;
+        }
+        output_file.flush().await.map_err(|e| CliError::WritingOutput(output_name(output), e))?;
+        if restore_attributes && !matches!(output, "-" | "^") {
+            let file = std::fs::OpenOptions::new().write(true).open(output).map_err(|e| CliError::OpeningOutput(output_name(output), e))?;
+            metadata.apply(&file).map_err(|e| CliError::WritingOutput(output_name(output), e))?;
+        }
+        Ok(())
+    }
+
     match &args.command {
-        Commands::Encrypt { file_path: input, output, secret_path } => {
-            let input_file = open_input(input).await.map_err(|e| CliError::OpeningInput(input_name(input), e))?;
-            let output_file = open_output(output).await.map_err(|e| CliError::OpeningOutput(output_name(output), e))?;
-            let mut secret_file = open_output(secret_path).await.map_err(|e| CliError::WritingSecret(output_name(secret_path), e))?;
+        Commands::Encrypt { file_path: input, output, secret_path, chunk_size, algorithm, passphrase, recipients, aad, compression_level, no_compression, padding, metadata, mime_type, key_commitment, memory_kib, iterations, parallelism } => {
+            let mut options = EncryptOptions::new().algorithm(*algorithm);
+            if let Some(aad) = aad {
+                options = options.associated_data(read_associated_data(aad).await?);
+            }
+            if let Some(chunk_size) = chunk_size {
+                options = options.chunk_size(*chunk_size as usize);
+            }
+            if let (Some(level), false) = (compression_level, no_compression) {
+                options = options.compression(Compression::Zstd(*level));
+            }
+            if let Some(padding) = padding {
+                options = options.padding(*padding);
+            }
+            if *metadata || mime_type.is_some() {
+                let mut file_metadata = match *metadata && input != "-" {
+                    true => Metadata::from_path(input.as_ref()).map_err(|e| CliError::OpeningInput(input_name(input), e))?,
+                    false => Metadata::new(),
+                };
+                file_metadata.mime_type = mime_type.as_ref().map(|mime_type| mime_type.to_string());
+                options = options.metadata(file_metadata);
+            }
+            if *key_commitment {
+                options = options.key_commitment(true);
+            }
+            if *passphrase {
+                let params = PassphraseParams { memory_kib: *memory_kib, iterations: *iterations, parallelism: *parallelism };
+                options = options.recipient(Recipient::Passphrase(read_passphrase(true)?, params));
+            }
+            for recipient in recipients {
+                options = options.recipient(match recipient.starts_with("gaia-public-") {
+                    true => Recipient::X25519(from_public_key(recipient).map_err(CliError::InvalidSecret)?),
+                    false => Recipient::Handle(from_secret(recipient).map_err(CliError::InvalidSecret)?),
+                });
+            }
+
+            // The recipients can decrypt without the secret, so it is only written when asked for
+            let secret_path = match secret_path {
+                Some(path) => Some(path.as_ref()),
+                None if *passphrase || !recipients.is_empty() => None,
+                None => Some("-"),
+            };
 
-            let handle = encrypt_async(input_file, output_file).await?;
+            // Regular files on both ends are memory mapped instead of being copied through readers
+            let mapped = is_regular_file(input, false) && is_regular_file(output, true);
+            let files = match mapped {
+                true => None,
+                false => Some((
+                    open_input(input).await.map_err(|e| CliError::OpeningInput(input_name(input), e))?,
+                    open_output(output).await.map_err(|e| CliError::OpeningOutput(output_name(output), e))?,
+                )),
+            };
+            let mut secret_file = match secret_path {
+                Some(path) => Some(open_output(path).await.map_err(|e| CliError::WritingSecret(output_name(path), e))?),
+                None => None,
+            };
 
-            let secret = to_secret(&handle).map_err(|e| CliError::InvalidSecret(e))?;
-            secret_file.write_all(secret.as_bytes()).await.map_err(|e| CliError::WritingSecret(output_name(secret_path), e))?;
-            secret_file.write(&[10]).await?;
+            let handle = match files {
+                Some((input_file, output_file)) => encrypt_async_with_options(input_file, output_file, &options).await?,
+                None => {
+                    let (input, output) = (input.to_string(), output.to_string());
+                    tokio::task::spawn_blocking(move || encrypt_file(input, output, &options)).await??
+                }
+            };
+
+            if let (Some(path), Some(secret_file)) = (secret_path, secret_file.as_mut()) {
+                let secret = to_secret(&handle).map_err(|e| CliError::InvalidSecret(e))?;
+                secret_file.write_all(secret.as_bytes()).await.map_err(|e| CliError::WritingSecret(output_name(path), e))?;
+                secret_file.write(&[10]).await?;
+            }
 
             Ok(())
         }
-        Commands::Decrypt { secret_key, file_path: input, output } => {
-            let input_file = open_input(input).await.map_err(|e| CliError::OpeningInput(input_name(input), e))?;
-            let output_file = open_output(output).await.map_err(|e| CliError::OpeningOutput(output_name(output), e))?;
+        Commands::Decrypt { secret_key, file_path, output, passphrase, identity_paths, aad, restore_name, restore_attributes, verify_first } => {
+            let (input, options) = read_decrypt_options("decrypt", secret_key, file_path, *passphrase, identity_paths, aad).await?;
+
+            // Verifying first reads regular files twice, and keeps a copy of anything else meanwhile
+            let input_error = |e| CliError::OpeningInput(input_name(input), e);
+            match (*verify_first, is_regular_file(input, false)) {
+                (false, _) => {
+                    let reader = AsyncDecryptingReader::with_options(open_input(input).await.map_err(input_error)?, &options);
+                    write_decrypted(reader, output, *restore_name, *restore_attributes).await
+                }
+                (true, true) => {
+                    let file = tokio::fs::File::open(input).await.map_err(input_error)?;
+                    let reader = AsyncDecryptingReader::verify_seekable(file, &options).await.map_err(GaiaError::from_input_error)?;
+                    write_decrypted(reader, output, *restore_name, *restore_attributes).await
+                }
+                (true, false) => {
+                    let input_file = open_input(input).await.map_err(input_error)?;
+                    let reader = AsyncDecryptingReader::verify_first(input_file, &options, VERIFY_MEMORY_LIMIT).await.map_err(GaiaError::from_input_error)?;
+                    write_decrypted(reader, output, *restore_name, *restore_attributes).await
+                }
+            }
+        }
+        Commands::Verify { secret_key, file_path, passphrase, identity_paths, aad } => {
+            let (input, options) = read_decrypt_options("verify", secret_key, file_path, *passphrase, identity_paths, aad).await?;
+            let mut input_file = open_input(input).await.map_err(|e| CliError::OpeningInput(input_name(input), e))?;
 
-            Ok(decrypt_async(input_file, &from_secret(secret_key.as_ref())
-                .map_err(|e| CliError::InvalidSecret(e))?, output_file).await?)
+            // The header is read ahead to tell whether the file commits to its key, and then verified along with the rest
+            let mut header = Vec::with_capacity(HEADER_LENGTH);
+            (&mut input_file).take(HEADER_LENGTH as u64).read_to_end(&mut header).await.map_err(|e| CliError::OpeningInput(input_name(input), e))?;
+            let committed = Header::from_bytes(&header).is_ok_and(|header| header.flags & FLAG_KEY_COMMITMENT != 0);
+            let error = match verify_async_with_options(io::Cursor::new(header).chain(input_file), &options).await {
+                Ok(()) => {
+                    eprintln!("The {} is intact", input_name(input));
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+
+            let Some(code) = verify_exit_code(&error, committed) else {
+                return Err(error.into());
+            };
+            eprintln!("Error: {error}");
+            std::process::exit(code)
+        }
+        Commands::Inspect { file_path: input, json } => {
+            let layout = match input.as_ref() {
+                "-" => inspect(io::stdin().lock()),
+                path => inspect(std::fs::File::open(path).map_err(|e| CliError::OpeningInput(input_name(path), e))?),
+            }?;
+            let header = layout.header;
+            let flags = flag_names(header.flags);
+
+            if *json {
+                println!("{}", layout_json(&layout));
+                return Ok(());
+            }
+
+            println!("Format:         Gaia version {}", header.version);
+            println!("Algorithm:      {}", header.algorithm);
+            println!("Flags:          {}", if flags.is_empty() { "none".to_string() } else { flags.join(", ") });
+            println!("Recipients:     {}", layout.recipients);
+            println!("Chunk size:     {} bytes", header.chunk_size);
+            println!("Preamble:       {} bytes", layout.preamble_length);
+            println!("Full chunks:    {}", layout.full_chunks);
+            match layout.final_chunk_length {
+                Some(length) => println!("Final chunk:    {length} bytes"),
+                None => println!("Final chunk:    missing"),
+            }
+            match header.flags & (FLAG_ZSTD | FLAG_PADDED) {
+                0 => println!("Plaintext:      {} bytes", layout.plaintext_length),
+                _ => println!("Plaintext:      {} bytes, before decompressing or unpadding", layout.plaintext_length),
+            }
+            match layout.trailing_bytes {
+                0 => println!("Trailing bytes: none"),
+                trailing_bytes => println!("Trailing bytes: {trailing_bytes}, so the file has been cut short or added to"),
+            }
+            Ok(())
+        }
+        Commands::Keygen { output } => {
+            let secret = StaticSecret::random_from_rng(rand_core::OsRng);
+            let public_key = to_public_key(&PublicKey::from(&secret));
+
+            let mut output_file = open_output(output).await.map_err(|e| CliError::OpeningOutput(output_name(output), e))?;
+            #[cfg(unix)]
+            if !matches!(output.as_ref(), "-" | "^") {
+                // Only the owner should be able to read the identity, like with SSH keys
+                use std::os::unix::fs::PermissionsExt;
+                tokio::fs::set_permissions(output.as_ref(), std::fs::Permissions::from_mode(0o600)).await
+                    .map_err(|e| CliError::OpeningOutput(output_name(output), e))?;
+            }
+            let contents = format!("# public key: {public_key}\n{}\n", to_identity(&secret));
+            output_file.write_all(contents.as_bytes()).await.map_err(|e| CliError::WritingOutput(output_name(output), e))?;
+            output_file.flush().await?;
+
+            if output != "-" {
+                eprintln!("Public key: {public_key}");
+            }
+            Ok(())
+        }
+        Commands::Seal { message, key, aad } => {
+            let key = match key {
+                Some(key) => from_key(key).map_err(CliError::InvalidSecret)?,
+                None => {
+                    let key = Key::generate(&mut rand_core::OsRng);
+                    eprintln!("Key: {}", to_key(&key));
+                    key
+                }
+            };
+            let aad = match aad {
+                Some(aad) => read_associated_data(aad).await?,
+                None => vec![],
+            };
+
+            let message = read_argument(message).await?;
+            println!("{}", to_sealed(&seal(&key, message.as_bytes(), &aad)));
+            Ok(())
+        }
+        Commands::Open { key, sealed, aad } => {
+            let key = from_key(key).map_err(CliError::InvalidSecret)?;
+            let aad = match aad {
+                Some(aad) => read_associated_data(aad).await?,
+                None => vec![],
+            };
+
+            let sealed = from_sealed(read_argument(sealed).await?.trim()).map_err(CliError::InvalidSecret)?;
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&open(&key, &sealed, &aad)?).await.map_err(|e| CliError::WritingOutput(output_name("-"), e))?;
+            stdout.write_all(b"\n").await.map_err(|e| CliError::WritingOutput(output_name("-"), e))?;
+            stdout.flush().await?;
+            Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use gaia::{encrypt_with_options, generate_handle, header::inspect, verify_with_options, DecryptOptions, EncryptOptions, Handle, Identity};
+
+    use super::*;
+
+    const CHUNK_SIZE: usize = 1024;
+
+    /// Encrypts three and a half chunks, returning the ciphertext, its handle and where its chunks start.
+    fn encrypted(key_commitment: bool) -> (Vec<u8>, Handle, usize) {
+        let options = EncryptOptions::new().chunk_size(CHUNK_SIZE).key_commitment(key_commitment);
+        let mut ciphertext = vec![];
+        let handle = encrypt_with_options([7u8; CHUNK_SIZE * 7 / 2].as_slice(), &mut ciphertext, &options).unwrap();
+        let preamble_length = inspect(ciphertext.as_slice()).unwrap().preamble_length as usize;
+        (ciphertext, handle, preamble_length)
+    }
+
+    fn exit_code(ciphertext: &[u8], handle: &Handle, committed: bool) -> Option<i32> {
+        let options = DecryptOptions::new().identity(Identity::Handle(handle.clone()));
+        verify_exit_code(&verify_with_options(ciphertext, &options).unwrap_err(), committed)
+    }
+
+    #[test]
+    fn wrong_key() {
+        let other = generate_handle(&mut rand_core::OsRng);
+        let (ciphertext, _, _) = encrypted(false);
+        assert_eq!(exit_code(&ciphertext, &other, false), Some(EXIT_WRONG_KEY));
+        let (ciphertext, _, _) = encrypted(true);
+        assert_eq!(exit_code(&ciphertext, &other, true), Some(EXIT_WRONG_KEY));
+    }
+
+    #[test]
+    fn tampered_first_chunk() {
+        // Without a commitment, a tampered first chunk cannot be told from a wrong key
+        let (mut ciphertext, handle, chunks_start) = encrypted(false);
+        ciphertext[chunks_start] ^= 1;
+        assert_eq!(exit_code(&ciphertext, &handle, false), Some(EXIT_WRONG_KEY));
+
+        let (mut ciphertext, handle, chunks_start) = encrypted(true);
+        ciphertext[chunks_start] ^= 1;
+        assert_eq!(exit_code(&ciphertext, &handle, true), Some(EXIT_CORRUPTED));
+    }
+
+    #[test]
+    fn tampered_later_chunk() {
+        let (mut ciphertext, handle, chunks_start) = encrypted(false);
+        ciphertext[chunks_start + 2 * (CHUNK_SIZE + 16)] ^= 1;
+        assert_eq!(exit_code(&ciphertext, &handle, false), Some(EXIT_CORRUPTED));
+    }
+
+    #[test]
+    fn truncated() {
+        let (ciphertext, handle, chunks_start) = encrypted(false);
+        assert_eq!(exit_code(&ciphertext[..chunks_start + 2 * (CHUNK_SIZE + 16)], &handle, false), Some(EXIT_TRUNCATED));
+        assert_eq!(exit_code(&ciphertext[..HEADER_LENGTH - 1], &handle, false), Some(EXIT_TRUNCATED));
+    }
+
+    #[test]
+    fn unreadable_input() {
+        let error = GaiaError::ReadingInput(io::Error::other("unreadable"));
+        assert_eq!(verify_exit_code(&error, false), None);
+    }
+}